@@ -1,13 +1,76 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
-use log::debug;
+use log::{debug, info, warn};
 use misc_conf::{apache::Apache, ast::Directive};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+use crate::error::{ParserError, ParserResult};
+
+pub mod access;
+pub mod apache;
+pub mod auth;
+pub mod header;
+mod nginx;
+pub mod profile;
+pub mod re2;
+pub mod redirect;
+pub mod registry;
+pub mod rewrite;
+pub mod tls;
+pub mod traefik;
+
+use access::{Access, AccessRule, Requirements};
+use auth::{AuthConfig, OidcConfig, ProtectedPath};
+use header::Header;
+use profile::{Profile, Settings, FORWARD_AUTH_STUB};
+use redirect::Redirect;
+use registry::MiddlewareRegistry;
+use rewrite::{RewriteCond, RewriteMap, RewriteRule, Translation};
+
+use traefik::{
+    Certificate, DynamicConfig, ForwardingTimeouts, LoadBalancer, Middleware, ReplacePath,
+    ReplacePathRegex, ResponseForwarding, Router, RouterTls, Server, ServersTransport, Service,
+    Sticky, StickyCookie, StripPrefix, TlsConfig, TlsOptions,
+};
+
+/// Priority of the routers generated for `RewriteRule [P]`, above any
+/// `ProxyPass` router.
+const REWRITE_PRIORITY: i64 = 10_000;
+
+/// Added to the priority of nginx's `location ^~` prefixes, which are
+/// matched before any regex location.
+const PREFERRED_PRIORITY: i64 = 1_000;
+
+/// Added to the priority of nginx's `location =`, which is matched before
+/// any other location.
+const EXACT_PRIORITY: i64 = 2_000;
+
+/// The suffixes of the middlewares generated from access rules, which a
+/// section with rules of its own replaces.
+const ACCESS_MIDDLEWARES: [&str; 3] = ["-ipallowlist", "-basicauth", "-forwardauth"];
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ProxyConfig {
     pub virtual_hosts: Vec<VirtualHost>,
+    /// Balancers declared outside of any virtual host.
+    balancers: Vec<Balancer>,
+    /// `SSLProxy*` settings given outside of any virtual host.
+    ssl_proxy: SslProxyConfig,
+    /// Timeouts given outside of any virtual host.
+    timeouts: Timeouts,
+    /// `SSL*` settings given outside of any virtual host.
+    ssl_config: SslConfig,
+    /// mod_auth_openidc settings given outside of any virtual host.
+    oidc: OidcConfig,
+    /// Middlewares shared by every host, from the registry.
+    middlewares: BTreeMap<String, Middleware>,
+    /// The profile defaults, for what isn't rendered per host.
+    settings: Settings,
     meta: HashMap<String, String>,
 }
 
@@ -16,6 +79,75 @@ impl ProxyConfig {
         self.virtual_hosts.push(virtual_host);
     }
 
+    /// Adds the hosts and server level settings parsed from another file.
+    pub fn merge(&mut self, other: ProxyConfig) {
+        self.virtual_hosts.extend(other.virtual_hosts);
+        self.middlewares.extend(other.middlewares);
+        for balancer in other.balancers {
+            merge_balancer(&mut self.balancers, balancer);
+        }
+        // later files override earlier ones, like later directives do
+        let mut ssl_proxy = other.ssl_proxy;
+        ssl_proxy.inherit(&self.ssl_proxy);
+        self.ssl_proxy = ssl_proxy;
+        let mut timeouts = other.timeouts;
+        timeouts.inherit(&self.timeouts);
+        self.timeouts = timeouts;
+        let mut ssl_config = other.ssl_config;
+        ssl_config.inherit(&self.ssl_config);
+        self.ssl_config = ssl_config;
+        let mut oidc = other.oidc;
+        oidc.inherit(&self.oidc);
+        self.oidc = oidc;
+        self.meta.extend(other.meta);
+    }
+
+    /// Copies server level settings into the virtual hosts using them, so
+    /// every host can be rendered on its own. Call once all files are
+    /// loaded, as a host may refer to a balancer declared in a later file.
+    pub fn inherit_server_config(&mut self) {
+        for virtual_host in &mut self.virtual_hosts {
+            virtual_host.link_upstreams(&self.balancers);
+            virtual_host.link_balancers(&self.balancers);
+            virtual_host.ssl_proxy.inherit(&self.ssl_proxy);
+            virtual_host.timeouts.inherit(&self.timeouts);
+            virtual_host.ssl_config.inherit(&self.ssl_config);
+            virtual_host.oidc.inherit(&self.oidc);
+        }
+        let https_names: HashSet<String> = self
+            .virtual_hosts
+            .iter()
+            .filter(|virtual_host| virtual_host.ssl_config.enabled)
+            .map(|virtual_host| virtual_host.name())
+            .collect();
+        let mut hosts_per_name: HashMap<String, usize> = HashMap::new();
+        for virtual_host in &self.virtual_hosts {
+            *hosts_per_name.entry(virtual_host.name()).or_default() += 1;
+        }
+        for virtual_host in &mut self.virtual_hosts {
+            let name = virtual_host.name();
+            virtual_host.https_twin =
+                !virtual_host.ssl_config.enabled && https_names.contains(&name);
+            virtual_host.port_in_name = hosts_per_name[&name] > 1;
+        }
+    }
+
+    /// Records the file the virtual hosts were read from.
+    pub fn set_source(&mut self, source: &Path) {
+        for virtual_host in &mut self.virtual_hosts {
+            virtual_host.source = Some(source.to_path_buf());
+        }
+    }
+
+    /// Takes the shared middlewares of the registry and attaches them to the
+    /// hosts its rules match.
+    pub fn apply_registry(&mut self, registry: &MiddlewareRegistry) {
+        self.middlewares = registry.middlewares.clone();
+        for virtual_host in &mut self.virtual_hosts {
+            virtual_host.middlewares = registry.middlewares_for(virtual_host);
+        }
+    }
+
     pub fn to_json(&self) -> Vec<serde_json::Value> {
         self.virtual_hosts
             .clone()
@@ -23,6 +155,153 @@ impl ProxyConfig {
             .map(|virtual_host| virtual_host.into())
             .collect()
     }
+
+    /// Builds a single Traefik dynamic configuration covering every virtual
+    /// host along with the middlewares their routers refer to.
+    pub fn to_traefik_config(&self) -> DynamicConfig {
+        let mut config = DynamicConfig::default();
+        config.http.middlewares = self.middlewares.clone();
+        for virtual_host in &self.virtual_hosts {
+            if let Some(host_config) = virtual_host.to_traefik_config() {
+                config.merge(host_config);
+            }
+        }
+        config
+    }
+
+    /// Applies the profile defaults and the overrides matching each host.
+    /// Middlewares set by the profile replace the ones attached by the
+    /// registry, so apply the registry first.
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        self.settings = profile.defaults();
+        for virtual_host in &mut self.virtual_hosts {
+            virtual_host.settings = profile.settings_for(virtual_host);
+            if let Some(middlewares) = &virtual_host.settings.middlewares {
+                virtual_host.middlewares = middlewares.clone();
+            }
+        }
+    }
+
+    /// Reads the htpasswd files of the `AuthUserFile` directives, resolving
+    /// relative paths against `server_root`. Files that can't be read are
+    /// reported and left for traefik to read as `usersFile`.
+    pub fn load_user_files(&mut self, server_root: Option<&Path>) {
+        let mut files: HashMap<PathBuf, Option<Vec<String>>> = HashMap::new();
+        for virtual_host in &mut self.virtual_hosts {
+            let source = virtual_host.source.clone();
+            for location in &mut virtual_host.locations {
+                let auth = &mut location.auth;
+                let Some(path) = auth.resolve_user_file(server_root, source.as_deref()) else {
+                    continue;
+                };
+                let path = path.to_path_buf();
+                let users = files
+                    .entry(path.clone())
+                    .or_insert_with(|| match auth::read_htpasswd(&path) {
+                        Ok(users) => Some(users),
+                        Err(err) => {
+                            warn!(
+                                "{}: {err}, traefik is pointed at the file instead",
+                                path.display()
+                            );
+                            None
+                        }
+                    });
+                auth.users = users.clone();
+            }
+        }
+    }
+
+    /// Makes the certificate files of the hosts absolute.
+    pub fn resolve_certificate_files(&mut self, server_root: Option<&Path>) {
+        for virtual_host in &mut self.virtual_hosts {
+            let source = virtual_host.source.as_deref();
+            virtual_host.ssl_config.resolve_files(server_root, source);
+        }
+    }
+
+    /// Writes the combined certificate and chain files traefik is pointed
+    /// at, or reports the ones that don't exist yet when `write` isn't set.
+    pub fn write_certificate_chains(&self, write: bool) -> ParserResult<()> {
+        let mut seen = HashMap::new();
+        for virtual_host in &self.virtual_hosts {
+            let ssl_config = &virtual_host.ssl_config;
+            let (Some(combined_file), Some(chain_file)) =
+                (ssl_config.combined_file(), &ssl_config.chain_file)
+            else {
+                continue;
+            };
+            let pair = (&ssl_config.certificate_file, chain_file);
+            match seen.insert(combined_file.clone(), pair) {
+                Some(previous) if previous != pair => {
+                    return Err(ParserError::Config(format!(
+                        "{combined_file} would combine both {} with {} and {} with {}",
+                        previous.0, previous.1, pair.0, pair.1
+                    )))
+                }
+                Some(_) => continue,
+                None => {}
+            }
+            if !write {
+                if !Path::new(&combined_file).exists() {
+                    warn!(
+                        "{combined_file} doesn't exist, create it with `cat {} {chain_file} > {combined_file}` or --write-chains",
+                        ssl_config.certificate_file
+                    );
+                }
+                continue;
+            }
+            let mut combined = std::fs::read(&ssl_config.certificate_file)?;
+            if !combined.ends_with(b"\n") {
+                combined.push(b'\n');
+            }
+            combined.extend(std::fs::read(chain_file)?);
+            info!("Writing certificate chain {combined_file}");
+            std::fs::write(&combined_file, combined)?;
+        }
+        Ok(())
+    }
+
+    /// A copy for printing, with secrets such as password hashes replaced.
+    pub fn redacted(&self) -> Self {
+        let mut configs = self.clone();
+        for virtual_host in &mut configs.virtual_hosts {
+            for location in &mut virtual_host.locations {
+                location.auth.redact();
+            }
+        }
+        configs
+    }
+
+    /// The key prefix of everything not written per host.
+    pub fn key_prefix(&self) -> &str {
+        &self.settings.key_prefix
+    }
+
+    /// The shared middlewares and the certificates of every host, which
+    /// are written once rather than per host.
+    pub fn to_shared_config(&self) -> DynamicConfig {
+        let mut config = DynamicConfig::default();
+        config.http.middlewares = self.middlewares.clone();
+        for virtual_host in &self.virtual_hosts {
+            let mut tls = TlsConfig::default();
+            tls.certificates
+                .extend(virtual_host.ssl_config.certificate());
+            config.merge(DynamicConfig {
+                tls,
+                ..Default::default()
+            });
+        }
+        config
+    }
+
+    /// Every middleware of the traefik config: the shared ones and the ones
+    /// generated for each host.
+    pub fn to_middleware_config(&self) -> DynamicConfig {
+        let mut config = DynamicConfig::default();
+        config.http.middlewares = self.to_traefik_config().http.middlewares;
+        config
+    }
 }
 
 impl Display for ProxyConfig {
@@ -44,11 +323,31 @@ pub struct VirtualHost {
     locations: Vec<Location>,
     rewrite_engine: bool,
     rewrite_rules: Vec<RewriteRule>,
+    rewrite_maps: Vec<RewriteMap>,
+    redirects: Vec<Redirect>,
+    proxy_passes: Vec<ProxyPass>,
+    proxy_pass_reverses: Vec<ProxyPassReverse>,
+    balancers: Vec<Balancer>,
     ssl_config: SslConfig,
+    ssl_proxy: SslProxyConfig,
+    timeouts: Timeouts,
+    oidc: OidcConfig,
     env: HashMap<String, String>,
-    headers: HashMap<String, Vec<String>>,
+    headers: Vec<Header>,
     listen: Vec<String>,
     log_level: String,
+    /// The file the host was read from.
+    source: Option<PathBuf>,
+    /// Shared middlewares attached by the registry.
+    middlewares: Vec<String>,
+    /// What the profile sets for this host.
+    settings: Settings,
+    /// Whether this is the plain http host of a site that also has an https
+    /// one, which is then only served on the redirect entry points.
+    https_twin: bool,
+    /// Whether the name is shared with a host on another port, which then
+    /// tells their routers and services apart.
+    port_in_name: bool,
 }
 
 impl VirtualHost {
@@ -67,11 +366,22 @@ pub struct VirtualHostBuilder {
     locations: Vec<Location>,
     rewrite_engine: bool,
     rewrite_rules: Vec<RewriteRule>,
+    rewrite_maps: Vec<RewriteMap>,
+    redirects: Vec<Redirect>,
+    proxy_passes: Vec<ProxyPass>,
+    proxy_pass_reverses: Vec<ProxyPassReverse>,
+    balancers: Vec<Balancer>,
     ssl_config: SslConfig,
+    ssl_proxy: SslProxyConfig,
+    timeouts: Timeouts,
+    oidc: OidcConfig,
     env: HashMap<String, String>,
-    headers: HashMap<String, Vec<String>>,
+    headers: Vec<Header>,
     listen: Vec<String>,
     log_level: String,
+    source: Option<PathBuf>,
+    middlewares: Vec<String>,
+    settings: Settings,
 }
 
 impl VirtualHostBuilder {
@@ -115,17 +425,57 @@ impl VirtualHostBuilder {
         self
     }
 
+    pub fn rewrite_maps(mut self, rewrite_maps: Vec<RewriteMap>) -> Self {
+        self.rewrite_maps = rewrite_maps;
+        self
+    }
+
+    pub fn redirects(mut self, redirects: Vec<Redirect>) -> Self {
+        self.redirects = redirects;
+        self
+    }
+
+    pub fn proxy_passes(mut self, proxy_passes: Vec<ProxyPass>) -> Self {
+        self.proxy_passes = proxy_passes;
+        self
+    }
+
+    pub fn proxy_pass_reverses(mut self, proxy_pass_reverses: Vec<ProxyPassReverse>) -> Self {
+        self.proxy_pass_reverses = proxy_pass_reverses;
+        self
+    }
+
+    pub fn balancers(mut self, balancers: Vec<Balancer>) -> Self {
+        self.balancers = balancers;
+        self
+    }
+
     pub fn ssl_config(mut self, ssl_config: SslConfig) -> Self {
         self.ssl_config = ssl_config;
         self
     }
 
+    pub fn ssl_proxy(mut self, ssl_proxy: SslProxyConfig) -> Self {
+        self.ssl_proxy = ssl_proxy;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn oidc(mut self, oidc: OidcConfig) -> Self {
+        self.oidc = oidc;
+        self
+    }
+
     pub fn env(mut self, env: HashMap<String, String>) -> Self {
         self.env = env;
         self
     }
 
-    pub fn headers(mut self, headers: HashMap<String, Vec<String>>) -> Self {
+    pub fn headers(mut self, headers: Vec<Header>) -> Self {
         self.headers = headers;
         self
     }
@@ -140,6 +490,21 @@ impl VirtualHostBuilder {
         self
     }
 
+    pub fn source(mut self, source: PathBuf) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn middlewares(mut self, middlewares: Vec<String>) -> Self {
+        self.middlewares = middlewares;
+        self
+    }
+
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
     pub fn build(self) -> VirtualHost {
         VirtualHost {
             host: self.host,
@@ -150,11 +515,24 @@ impl VirtualHostBuilder {
             locations: self.locations,
             rewrite_engine: self.rewrite_engine,
             rewrite_rules: self.rewrite_rules,
+            rewrite_maps: self.rewrite_maps,
+            redirects: self.redirects,
+            proxy_passes: self.proxy_passes,
+            proxy_pass_reverses: self.proxy_pass_reverses,
+            balancers: self.balancers,
             ssl_config: self.ssl_config,
+            ssl_proxy: self.ssl_proxy,
+            timeouts: self.timeouts,
+            oidc: self.oidc,
             env: self.env,
             headers: self.headers,
             listen: self.listen,
             log_level: self.log_level,
+            source: self.source,
+            middlewares: self.middlewares,
+            settings: self.settings,
+            https_twin: false,
+            port_in_name: false,
         }
     }
 }
@@ -184,6 +562,31 @@ impl From<&HashMap<String, String>> for VirtualHost {
 }
 
 impl VirtualHost {
+    /// The server name without any scheme prefix.
+    pub fn name(&self) -> String {
+        self.server_name
+            .replace("http://", "")
+            .replace("https://", "")
+    }
+
+    /// The server name in the dashed form used for router, service and
+    /// file names. When another host has the same name, such as the http
+    /// and https hosts of a site, the port of the address is added.
+    pub fn dashed_name(&self) -> String {
+        let dashed = self.name().replace('.', "-");
+        match self.port().filter(|_| self.port_in_name) {
+            Some(port) => format!("{dashed}-{port}"),
+            None => dashed,
+        }
+    }
+
+    /// The port of the `<VirtualHost>` address, e.g. `443` for
+    /// `10.0.0.1:443` or `*:443`.
+    fn port(&self) -> Option<&str> {
+        let (_, port) = self.host.rsplit_once(':')?;
+        (!port.is_empty() && port.chars().all(|c| c.is_ascii_digit())).then_some(port)
+    }
+
     pub fn get_host_and_port(&self) -> (String, String, String) {
         let host = self.host.clone();
         let (host, port) = host.split_once(":").unwrap_or((&self.host, "80"));
@@ -198,122 +601,902 @@ impl VirtualHost {
         (host.to_string(), port.to_string(), url.to_string())
     }
 
+    /// The key/value pairs written to etcd for this host. Certificates are
+    /// numbered across hosts, so they are left to the shared config.
+    pub fn to_etcd_kv_pairs(&self) -> Vec<(String, String)> {
+        self.to_traefik_config()
+            .map(|mut config| {
+                config.tls.certificates.clear();
+                config.to_kv_pairs(&self.settings.key_prefix)
+            })
+            .unwrap_or_default()
+    }
+
     pub fn to_etcd_config(&self) -> String {
-        let mut config = String::new();
         debug!("VirtualHost: {:#?}", self);
+        etcdctl_commands(&self.to_etcd_kv_pairs())
+    }
+
+    /// The upstreams this host proxies to, taken from its `ProxyPass`
+    /// rules. Hosts without any fall back to their own address.
+    pub fn backends(&self) -> Vec<Upstream> {
+        let mut backends: Vec<Upstream> = vec![];
+        for proxy_pass in &self.proxy_passes {
+            let Some(upstream) = proxy_pass.upstream() else {
+                continue;
+            };
+            // a balancer stands for all of its members
+            let upstreams = match self.balancer_for(&upstream) {
+                Some(balancer) => balancer
+                    .members
+                    .iter()
+                    .filter_map(|member| Upstream::parse(&member.url))
+                    .collect(),
+                None => vec![upstream],
+            };
+            for upstream in upstreams {
+                if !backends.iter().any(|b| b.base_url() == upstream.base_url()) {
+                    backends.push(upstream);
+                }
+            }
+        }
+        if backends.is_empty() {
+            backends.push(self.own_backend());
+        }
+        backends
+    }
+
+    /// The host's own address, where httpd serves what isn't proxied.
+    fn own_backend(&self) -> Upstream {
+        let (host, port, url) = self.get_host_and_port();
+        let scheme = url.split_once("://").map(|(s, _)| s).unwrap_or("http");
+        Upstream {
+            scheme: scheme.to_string(),
+            host,
+            port,
+            path: String::new(),
+        }
+    }
+
+    /// The backend serving `/`, or the first one when nothing is mounted
+    /// at the root.
+    pub fn primary_backend(&self) -> Upstream {
+        self.proxy_passes
+            .iter()
+            .filter(|proxy_pass| proxy_pass.path == "/")
+            .find_map(ProxyPass::upstream)
+            .unwrap_or_else(|| self.backends().remove(0))
+    }
+
+    /// The backend URL including the port when it is not implied by the
+    /// scheme.
+    pub fn backend_url(&self) -> String {
+        self.primary_backend().base_url()
+    }
+
+    /// The balancer a `balancer://name` upstream refers to.
+    fn balancer_for(&self, upstream: &Upstream) -> Option<&Balancer> {
+        if upstream.scheme != "balancer" {
+            return None;
+        }
+        self.balancers
+            .iter()
+            .find(|balancer| balancer.name == upstream.host)
+    }
+
+    /// Copies the server level balancers this host proxies to and applies
+    /// the balancer settings given on its `ProxyPass` lines, such as
+    /// `ProxyPass / balancer://app/ stickysession=JSESSIONID`.
+    fn link_balancers(&mut self, server_balancers: &[Balancer]) {
+        for proxy_pass in &self.proxy_passes {
+            let Some(upstream) = proxy_pass.upstream() else {
+                continue;
+            };
+            if upstream.scheme != "balancer" {
+                continue;
+            }
+            let index = match self
+                .balancers
+                .iter()
+                .position(|balancer| balancer.name == upstream.host)
+            {
+                Some(index) => index,
+                None => match server_balancers
+                    .iter()
+                    .find(|balancer| balancer.name == upstream.host)
+                {
+                    Some(balancer) => {
+                        self.balancers.push(balancer.clone());
+                        self.balancers.len() - 1
+                    }
+                    None => {
+                        warn!(
+                            "{}: ProxyPass {} refers to an undefined balancer {}",
+                            self.name(),
+                            proxy_pass.path,
+                            proxy_pass.url
+                        );
+                        continue;
+                    }
+                },
+            };
+            for (key, value) in &proxy_pass.params {
+                if Balancer::PARAMS.contains(&key.as_str()) {
+                    self.balancers[index]
+                        .params
+                        .entry(key.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+        }
+    }
+
+    pub fn to_traefik_config(&self) -> Option<DynamicConfig> {
         if self.server_name.is_empty() {
-            return config;
-        }
-        let name = self.server_name.clone();
-        let name = name.replace("http://", "");
-        let name = name.replace("https://", "");
-        let dashed_str = name.replace(".", "-");
-        config.push_str(&format!(
-            "etcdctl put traefik/http/routers/{dashed_str}/rule \"Host(\\`{name}\\`)\"\n"
-        ));
-        config.push_str(&format!(
-            "etcdctl put traefik/http/routers/{dashed_str}/tls \"true\"\n"
-        ));
-        config.push_str(&format!(
-            "etcdctl put traefik/http/routers/{dashed_str}/entryPoints/0 websecure\n"
-        ));
-        // Add middleware
-        config.push_str(&format!(
-            "etcdctl put traefik/http/routers/{dashed_str}/middlewares/0 https-only\n",
-        ));
-        config.push_str(&format!(
-            "etcdctl put traefik/http/routers/{dashed_str}/middlewares/1 follow-redirects\n",
-        ));
-
-        config.push_str(&format!(
-            "etcdctl put traefik/http/routers/{dashed_str}/service \"{dashed_str}\"\n",
-        ));
-        // let host = self.host.clone();
-        // let (host, port) = host.split_once(":").unwrap_or((&self.host, "80"));
-        // let mut url = format!("http://{}", host);
-        let (host, port, mut url) = self.get_host_and_port();
-        match port.as_str() {
-            "80" => {
-                config.push_str(&format!(
-                    "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/servers/0/scheme \"http\"\n",
-                ));
+            return None;
+        }
+        let name = self.name();
+        let dashed_str = self.dashed_name();
+        let transport_name = format!("{dashed_str}-transport");
+        let host_rule = format!("Host(`{name}`)");
+        let mut host_middlewares = self.middlewares.clone();
+
+        let mut config = DynamicConfig::default();
+
+        // access control runs before the host's own middlewares
+        let host_access = self.host_access_section();
+        if let Some(section) = host_access {
+            for (middleware_name, middleware) in self.access_middlewares(section, &dashed_str) {
+                config
+                    .http
+                    .middlewares
+                    .insert(middleware_name.clone(), middleware);
+                host_middlewares.push(middleware_name);
             }
-            "443" => {
-                config.push_str(&format!(
-                    "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/servers/0/scheme \"https\"\n",
-                ));
-                url = format!("https://{}", host);
+        }
+
+        let headers_name = format!("{dashed_str}-headers");
+        if let Some(middleware) = header::to_middleware(&name, &self.headers) {
+            config
+                .http
+                .middlewares
+                .insert(headers_name.clone(), middleware);
+            host_middlewares.push(headers_name);
+        }
+
+        // rewrite middlewares run on every router of the host, in the order
+        // of the rules; `[P]` rules get routers of their own which only run
+        // the rules before them
+        let mut rewrite_routes = vec![];
+        if self.rewrite_engine {
+            // the first rewrite whose `[L]` traefik can't honour, as later
+            // rules still run on the path it rewrote
+            let mut last_rule = None;
+            for (index, rule) in self.rewrite_rules.iter().enumerate() {
+                let rule_name = format!("{dashed_str}-rewrite-{}", index + 1);
+                let translation = rule.to_traefik();
+                if let (Some(stopping), Ok(_)) = (last_rule, &translation) {
+                    warn!(
+                        "{name}: {rule} still runs when {stopping} matches, traefik chains the rewrites past [L]"
+                    );
+                    last_rule = None;
+                }
+                match translation {
+                    Ok(Translation::Middleware(middleware)) => {
+                        if rule.flags.stops() && !rule.is_redirect() {
+                            last_rule = Some(rule);
+                        }
+                        config
+                            .http
+                            .middlewares
+                            .insert(rule_name.clone(), middleware);
+                        host_middlewares.push(rule_name);
+                    }
+                    Ok(Translation::Proxy {
+                        path_regex,
+                        upstream,
+                        middleware,
+                    }) => {
+                        let mut middlewares = host_middlewares.clone();
+                        config
+                            .http
+                            .middlewares
+                            .insert(rule_name.clone(), middleware);
+                        middlewares.push(rule_name.clone());
+                        rewrite_routes.push((index, rule_name, path_regex, upstream, middlewares));
+                    }
+                    Err(reason) => warn!("{name}: {rule} can't be translated: {reason}"),
+                }
             }
-            _ => {
-                url = format!("http://{}", host);
+        }
+        // mod_alias redirects run after the rewrites; redirecting the whole
+        // host takes a router of its own instead of the proxy routes
+        let mut whole_host_redirect = None;
+        let mut path_redirects = vec![];
+        for redirect in &self.redirects {
+            // named by path, as a host may redirect several of them
+            let redirect_name = format!("{dashed_str}-redirect-{}", slug(&redirect.path));
+            match redirect.to_middleware() {
+                Ok(middleware) => {
+                    config
+                        .http
+                        .middlewares
+                        .insert(redirect_name.clone(), middleware);
+                    if redirect.is_whole_host() {
+                        whole_host_redirect = Some(redirect_name);
+                        // later redirects can't match anymore
+                        break;
+                    }
+                    path_redirects.push((redirect_name, redirect));
+                }
+                Err(reason) => warn!(
+                    "{name}: Redirect {} can't be translated: {reason}",
+                    redirect.path
+                ),
             }
         }
-        config.push_str(&format!(
-            "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/servers/0/url \"{}\"\n",
-            url,
-        ));
+        host_middlewares.extend(path_redirects.iter().map(|(name, _)| name.clone()));
+        for location in &self.locations {
+            if !location.rewrite_rules.is_empty() {
+                warn!(
+                    "{name}: rewrite rules in {} are not translated, only server level rules are",
+                    location.path
+                );
+            }
+            // headers of a section only apply to the router proxying it
+            let routed = location.proxy_pass.is_some() && location.kind == LocationKind::Location;
+            if !location.headers.is_empty() && !routed {
+                warn!(
+                    "{name}: headers in {} are not translated, the section isn't proxied",
+                    location.path
+                );
+            }
+            // `<Directory />` is the usual lockdown of the file system,
+            // lifted again for the document root
+            let is_host_section =
+                host_access.is_some_and(|section| std::ptr::eq(section, location));
+            if !location.access_rules.is_empty()
+                && location.kind != LocationKind::Location
+                && !is_host_section
+                && location.path != "/"
+            {
+                warn!(
+                    "{name}: access rules in {} are not translated, traefik routes by path",
+                    location.path
+                );
+            }
+        }
+        // service name by backend URL, so paths sharing a backend share a
+        // service
+        let mut services: BTreeMap<(String, String), String> = BTreeMap::new();
+        let mut add_service = |config: &mut DynamicConfig,
+                               service_name: &str,
+                               upstream: &Upstream,
+                               transport: &str| {
+            // routes to the same backend share a service unless their
+            // transports differ
+            let url = upstream.base_url();
+            let key = (url.clone(), transport.to_string());
+            if let Some(existing) = services.get(&key) {
+                return existing.clone();
+            }
+            services.insert(key, service_name.to_string());
+            let (servers, sticky) = match self.balancer_for(upstream) {
+                Some(balancer) => (
+                    balancer.servers(&name),
+                    balancer.sticky(self.ssl_config.enabled),
+                ),
+                None => (vec![Server { url, weight: None }], None),
+            };
+            config.http.services.insert(
+                service_name.to_string(),
+                Service {
+                    load_balancer: LoadBalancer {
+                        servers,
+                        sticky,
+                        servers_transport: Some(transport.to_string()),
+                        response_forwarding: Some(ResponseForwarding {
+                            flush_interval: self.settings.flush_interval.clone(),
+                        }),
+                    },
+                },
+            );
+            service_name.to_string()
+        };
+
+        let routes = self
+            .proxy_passes
+            .iter()
+            .filter_map(|proxy_pass| {
+                let mut proxy_pass = proxy_pass.clone();
+                if proxy_pass.regex {
+                    match re2::to_re2(&proxy_pass.path) {
+                        Ok(path) => proxy_pass.path = path,
+                        Err(reason) => {
+                            warn!(
+                                "{name}: skipping {}, traefik can't compile it: {reason}",
+                                proxy_pass.path
+                            );
+                            return None;
+                        }
+                    }
+                }
+                // `ProxyPass /path !` leaves the path to httpd itself
+                let upstream = if proxy_pass.excluded {
+                    let own = self.own_backend();
+                    if matches!(own.host.as_str(), "" | "*" | "_default_") {
+                        warn!(
+                            "{name}: skipping ProxyPass {} !, the host has no address to route it to",
+                            proxy_pass.path
+                        );
+                        return None;
+                    }
+                    own
+                } else {
+                    proxy_pass.upstream()?
+                };
+                Some((proxy_pass, upstream))
+            })
+            .filter(|(proxy_pass, upstream)| {
+                if upstream.scheme == "balancer" {
+                    // undefined balancers are reported by `link_balancers`
+                    return self.balancer_for(upstream).is_some();
+                }
+                let supported = matches!(
+                    upstream.scheme.as_str(),
+                    "http" | "https" | "h2c" | "ws" | "wss"
+                );
+                if !supported {
+                    warn!(
+                        "{name}: ProxyPass {} {} uses a scheme traefik can't proxy to",
+                        proxy_pass.path, proxy_pass.url
+                    );
+                }
+                supported
+            })
+            .collect::<Vec<_>>();
 
-        config.push_str(&format!(
-            "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/servers/0/port {port}\n"
-        ));
+        // routers by the path prefix they serve, for the sections with
+        // access rules of their own
+        let mut path_routers: Vec<(String, String)> = vec![];
+        let has_root_route = routes.iter().any(|(proxy_pass, _)| proxy_pass.is_root());
+        if let Some(redirect_name) = whole_host_redirect {
+            if has_root_route {
+                warn!("{name}: Redirect / is shadowed by ProxyPass /");
+            } else {
+                let mut middlewares = path_redirects
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                middlewares.push(redirect_name);
+                config.http.routers.insert(
+                    format!("{dashed_str}-redirect"),
+                    self.redirect_router(host_rule.clone(), 1, middlewares),
+                );
+            }
+        } else if !routes.is_empty() && !has_root_route {
+            // nothing else catches the redirected paths
+            for (redirect_name, redirect) in &path_redirects {
+                let (rule, priority) = if redirect.regex {
+                    let Ok(pattern) = re2::to_re2(&redirect.path) else {
+                        continue;
+                    };
+                    (
+                        format!("{host_rule} && PathRegexp(`{pattern}`)"),
+                        10 + redirect.path.len(),
+                    )
+                } else {
+                    (
+                        format!("{host_rule} && PathPrefix(`{}`)", redirect.path),
+                        20 + redirect.path.len(),
+                    )
+                };
+                config.http.routers.insert(
+                    redirect_name.clone(),
+                    self.redirect_router(rule, priority as i64, vec![redirect_name.clone()]),
+                );
+            }
+        } else if routes.is_empty() {
+            let service = add_service(
+                &mut config,
+                &dashed_str,
+                &self.primary_backend(),
+                &transport_name,
+            );
+            config.http.routers.insert(
+                dashed_str.clone(),
+                Router {
+                    entry_points: self.entry_points(),
+                    rule: host_rule.clone(),
+                    middlewares: host_middlewares.clone(),
+                    service,
+                    tls: self.tls(),
+                    ..Default::default()
+                },
+            );
+            path_routers.push(("/".to_string(), dashed_str.clone()));
+        }
 
-        config.push_str(&format!(
-            "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/responseForwarding/flushInterval \"100ms\"\n"
-        ));
-        // Server transports
-        let transport_name = format!("{dashed_str}-transport");
-        // Configure the server transport
-        config.push_str(&format!(
-            "etcdctl put traefik/http/serversTransports/{transport_name}/insecureSkipVerify \"true\"\n",
-        ));
-        config.push_str(&format!(
-            "etcdctl put traefik/http/serversTransports/{transport_name}/forwardingTimeouts/responseHeaderTimeout \"30s\"\n",
-        ));
-        config.push_str(&format!(
-            "etcdctl put traefik/http/serversTransports/{transport_name}/forwardingTimeouts/idleConnTimeout \"30s\"\n",
-        ));
-
-        // Set the serverTransport
-        config.push_str(&format!(
-            "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/serversTransport \"{transport_name}\"\n",
-        ));
-
-        // config.push_str(&format!(
-        //     "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/serversTransport/forwardingTimeouts/responseHeaderTimeout \"30s\"\n"
-        // ));
-        // config.push_str(&format!(
-        //     "etcdctl put traefik/http/services/{dashed_str}/loadbalancer/serversTransport/insecureSkipVerify true\n"
-        // ));
+        // the root path keeps the plain host name so existing routers and
+        // services keep their names
+        for (proxy_pass, upstream) in routes
+            .iter()
+            .filter(|(proxy_pass, _)| proxy_pass.is_root())
+            .chain(
+                routes
+                    .iter()
+                    .filter(|(proxy_pass, _)| !proxy_pass.is_root()),
+            )
+        {
+            let router_name = if proxy_pass.is_root() {
+                dashed_str.clone()
+            } else {
+                unique_router_name(&config, format!("{dashed_str}-{}", slug(&proxy_pass.path)))
+            };
+            if proxy_pass.excluded {
+                warn!(
+                    "{name}: ProxyPass {} ! is routed to the host's own address {}",
+                    proxy_pass.path,
+                    upstream.base_url()
+                );
+            }
+            // `ProxyPass` timeouts differing from the host's need a
+            // transport of their own
+            let timeouts = self.forwarding_timeouts(&proxy_pass.params);
+            let transport = if timeouts == self.forwarding_timeouts(&BTreeMap::new()) {
+                transport_name.clone()
+            } else {
+                // the root router is named after the host, like its transport
+                let transport = if proxy_pass.is_root() {
+                    format!("{router_name}-root-transport")
+                } else {
+                    format!("{router_name}-transport")
+                };
+                let mut servers_transport = self.servers_transport();
+                servers_transport.forwarding_timeouts = Some(timeouts);
+                config
+                    .http
+                    .servers_transports
+                    .insert(transport.clone(), servers_transport);
+                transport
+            };
+            let service = add_service(&mut config, &router_name, upstream, &transport);
 
+            let mut middlewares = host_middlewares.clone();
+            let location = self.locations.iter().find(|location| {
+                location.kind == LocationKind::Location && location.path == proxy_pass.path
+            });
+            if let Some(location) = location {
+                let is_host_section =
+                    host_access.is_some_and(|section| std::ptr::eq(section, location));
+                if !location.access_rules.is_empty() && !is_host_section {
+                    self.add_section_access(&mut config, &mut middlewares, &router_name, location);
+                }
+                if let Some(middleware) = header::to_middleware(&name, &location.headers) {
+                    let middleware_name = format!("{router_name}-headers");
+                    config
+                        .http
+                        .middlewares
+                        .insert(middleware_name.clone(), middleware);
+                    middlewares.push(middleware_name);
+                }
+            }
+            let path_middleware = if proxy_pass.excluded {
+                None
+            } else {
+                proxy_pass.path_middleware(upstream)
+            };
+            if let Some((suffix, middleware)) = path_middleware {
+                let middleware_name = format!("{router_name}-{suffix}");
+                config
+                    .http
+                    .middlewares
+                    .insert(middleware_name.clone(), middleware);
+                middlewares.push(middleware_name);
+            }
+
+            let rule = match (proxy_pass.is_root(), proxy_pass.regex) {
+                (true, _) => host_rule.clone(),
+                (false, true) => format!("{host_rule} && PathRegexp(`{}`)", proxy_pass.path),
+                (false, false) => format!("{host_rule} && PathPrefix(`{}`)", proxy_pass.path),
+            };
+            if !proxy_pass.regex {
+                path_routers.push((proxy_pass.path.clone(), router_name.clone()));
+            }
+            config.http.routers.insert(
+                router_name,
+                Router {
+                    entry_points: self.entry_points(),
+                    rule,
+                    priority: Some(proxy_pass.priority()),
+                    middlewares,
+                    service,
+                    tls: self.tls(),
+                },
+            );
+        }
+
+        // sections with access rules but no router of their own get a copy
+        // of the router serving them
+        for location in &self.locations {
+            let is_host_section =
+                host_access.is_some_and(|section| std::ptr::eq(section, location));
+            if location.kind != LocationKind::Location
+                || location.access_rules.is_empty()
+                || is_host_section
+                || routes
+                    .iter()
+                    .any(|(proxy_pass, _)| proxy_pass.path == location.path)
+            {
+                continue;
+            }
+            let covering = path_routers
+                .iter()
+                .filter(|(path, _)| !location.regex && location.path.starts_with(path.as_str()))
+                .max_by_key(|(path, _)| path.len())
+                .and_then(|(_, router_name)| config.http.routers.get(router_name).cloned());
+            let Some(mut router) = covering else {
+                warn!(
+                    "{name}: access rules in {} are not translated, no router serves the section",
+                    location.path
+                );
+                continue;
+            };
+            let router_name =
+                unique_router_name(&config, format!("{dashed_str}-{}", slug(&location.path)));
+            self.add_section_access(&mut config, &mut router.middlewares, &router_name, location);
+            router.rule = format!("{host_rule} && PathPrefix(`{}`)", location.path);
+            router.priority = Some(20 + location.path.len() as i64 + location.precedence);
+            config.http.routers.insert(router_name, router);
+        }
+
+        // rewrites happen before `ProxyPass` is looked at, so `[P]` rules
+        // win over every path router
+        for (index, router_name, path_regex, upstream, middlewares) in rewrite_routes {
+            let service = add_service(&mut config, &router_name, &upstream, &transport_name);
+            config.http.routers.insert(
+                router_name,
+                Router {
+                    entry_points: self.entry_points(),
+                    rule: format!("{host_rule} && PathRegexp(`{path_regex}`)"),
+                    priority: Some(REWRITE_PRIORITY - index as i64),
+                    middlewares,
+                    service,
+                    tls: self.tls(),
+                },
+            );
+        }
+
+        let uses_transport = config.http.services.values().any(|service| {
+            service.load_balancer.servers_transport.as_ref() == Some(&transport_name)
+        });
+        if uses_transport {
+            config
+                .http
+                .servers_transports
+                .insert(transport_name, self.servers_transport());
+        }
         config
+            .tls
+            .certificates
+            .extend(self.ssl_config.certificate());
+        let mut notes = vec![];
+        config.tls.options.extend(self.tls_options(&mut notes));
+        for note in notes {
+            warn!("{}: {note}", self.name());
+        }
+        Some(config)
+    }
+
+    /// The section whose access rules cover the whole host: `<Location />`,
+    /// or the document root's `<Directory>` when nothing is proxied and
+    /// httpd serves the files itself.
+    fn host_access_section(&self) -> Option<&Location> {
+        let mut sections = self
+            .locations
+            .iter()
+            .filter(|location| !location.access_rules.is_empty() && !location.regex);
+        let document_root = self.document_root.trim_end_matches('/');
+        sections
+            .clone()
+            .find(|location| location.kind == LocationKind::Location && location.path == "/")
+            .or_else(|| {
+                sections.find(|location| {
+                    self.proxy_passes.is_empty()
+                        && location.kind == LocationKind::Directory
+                        && !document_root.is_empty()
+                        && location.path.trim_end_matches('/') == document_root
+                })
+            })
+    }
+
+    /// The middlewares enforcing the access rules of a section, named after
+    /// `prefix`: the allow list of client addresses, then the login. Logins
+    /// checked by an external provider go through `forwardAuth`. Rules and
+    /// logins that can't be translated refuse every client rather than
+    /// none.
+    fn access_middlewares(&self, section: &Location, prefix: &str) -> Vec<(String, Middleware)> {
+        let requirements = match access::evaluate(&section.access_rules) {
+            Ok(requirements) => requirements,
+            Err(reason) => {
+                warn!(
+                    "{}: access rules in {} can't be translated, requests are refused: {reason}",
+                    self.name(),
+                    section.path
+                );
+                Requirements::access(Access::Denied)
+            }
+        };
+        let mut middlewares = vec![];
+        if let Some(middleware) = access::to_middleware(&requirements.access) {
+            middlewares.push((format!("{prefix}-ipallowlist"), middleware));
+        }
+        if let Some(login) = &requirements.login {
+            let auth = self.section_auth(section);
+            if let Some(provider) = auth.provider() {
+                if self.settings.forward_auth_address == FORWARD_AUTH_STUB {
+                    warn!(
+                        "{}: {} is protected by {provider}, requests are refused until forward_auth_address is set in the profile",
+                        self.name(),
+                        section.path
+                    );
+                }
+                let middleware = auth::to_forward_auth(&provider, &self.name(), &self.settings);
+                middlewares.push((format!("{prefix}-forwardauth"), middleware));
+                return middlewares;
+            }
+            match auth.to_middleware(&self.name(), &section.path, login) {
+                Ok(middleware) => middlewares.push((format!("{prefix}-basicauth"), middleware)),
+                Err(reason) => {
+                    // nobody gets in rather than everybody
+                    warn!(
+                        "{}: {login} in {} isn't translated, requests are refused: {reason}",
+                        self.name(),
+                        section.path
+                    );
+                    let middleware = access::to_middleware(&Access::Denied)
+                        .expect("denied access has an allow list");
+                    return vec![(format!("{prefix}-ipallowlist"), middleware)];
+                }
+            }
+        }
+        middlewares
+    }
+
+    /// The `Auth*` directives of a section merged with the host's, as httpd
+    /// merges them with the enclosing sections.
+    fn section_auth(&self, section: &Location) -> AuthConfig {
+        let mut auth = section.auth.clone();
+        if let Some(host_section) = self.host_access_section() {
+            auth.inherit(&host_section.auth);
+        }
+        auth
+    }
+
+    /// The sections whose login is left to an external provider.
+    pub fn external_auth(&self) -> Vec<ProtectedPath> {
+        self.locations
+            .iter()
+            .filter_map(|section| {
+                access::evaluate(&section.access_rules).ok()?.login?;
+                let auth = self.section_auth(section);
+                Some(ProtectedPath {
+                    path: section.path.clone(),
+                    provider: auth.provider()?,
+                    detail: auth.provider_detail(&self.oidc),
+                })
+            })
+            .collect()
+    }
+
+    /// Puts the access middlewares of a section in place of the host's, as
+    /// the rules of a section replace the enclosing ones in Apache.
+    fn add_section_access(
+        &self,
+        config: &mut DynamicConfig,
+        middlewares: &mut Vec<String>,
+        router_name: &str,
+        location: &Location,
+    ) {
+        let access_middlewares = self.access_middlewares(location, router_name);
+        // the copy of a router can carry the middlewares of its section
+        let dashed_name = self.dashed_name();
+        let is_access = |name: &String| {
+            name.starts_with(&dashed_name)
+                && ACCESS_MIDDLEWARES
+                    .iter()
+                    .any(|suffix| name.ends_with(suffix))
+        };
+        let position = middlewares
+            .iter()
+            .position(is_access)
+            .unwrap_or(self.middlewares.len().min(middlewares.len()));
+        middlewares.retain(|name| !is_access(name));
+        for (offset, (middleware_name, middleware)) in access_middlewares.into_iter().enumerate() {
+            config
+                .http
+                .middlewares
+                .insert(middleware_name.clone(), middleware);
+            middlewares.insert(position + offset, middleware_name);
+        }
+    }
+
+    /// The timeouts towards the backends: the `ProxyPass` parameters given,
+    /// then the host's (or server's) timeout directives, then the profile.
+    /// Like in Apache the connect timeout defaults to the response one.
+    fn forwarding_timeouts(&self, params: &BTreeMap<String, String>) -> ForwardingTimeouts {
+        let param = |key: &str| params.get(key).and_then(|value| go_duration(value));
+        if params.contains_key("keepalive") {
+            debug!(
+                "{}: keepalive= only toggles TCP keepalives, traefik has no such setting",
+                self.name()
+            );
+        }
+        let response = param("timeout").or_else(|| self.timeouts.response());
+        ForwardingTimeouts {
+            dial_timeout: param("connectiontimeout")
+                .or(response.clone())
+                .or_else(|| self.settings.dial_timeout.clone()),
+            response_header_timeout: response
+                .or_else(|| self.settings.response_header_timeout.clone()),
+            idle_conn_timeout: param("ttl")
+                .or_else(|| self.timeouts.keep_alive_timeout.clone())
+                .or_else(|| self.settings.idle_conn_timeout.clone()),
+        }
+    }
+
+    /// The transport to the backends. Verification follows `SSLProxyVerify`
+    /// when it is given and the profile otherwise, since Apache doesn't
+    /// verify backends by default. The TLS settings are left out unless a
+    /// backend is reached over TLS or `SSLProxyEngine` is on.
+    fn servers_transport(&self) -> ServersTransport {
+        let ssl_proxy = &self.ssl_proxy;
+        let tls_backend = self
+            .backends()
+            .iter()
+            .any(|upstream| matches!(upstream.scheme.as_str(), "https" | "wss"));
+        if !tls_backend && ssl_proxy.enabled != Some(true) {
+            return ServersTransport {
+                insecure_skip_verify: self.settings.insecure_skip_verify,
+                forwarding_timeouts: Some(self.forwarding_timeouts(&BTreeMap::new())),
+                ..Default::default()
+            };
+        }
+        let verifies = ssl_proxy.verifies();
+        if verifies == Some(true) && ssl_proxy.check_peer_name == Some(false) {
+            warn!(
+                "{}: SSLProxyCheckPeerName off can't be expressed, traefik checks the backend name",
+                self.name()
+            );
+        }
+        // with ProxyPreserveHost the backend is asked for the client's host
+        let server_name = ssl_proxy.server_name.clone().or_else(|| {
+            ssl_proxy
+                .preserve_host
+                .unwrap_or_default()
+                .then(|| self.name())
+        });
+        ServersTransport {
+            server_name,
+            insecure_skip_verify: verifies
+                .map(|verifies| !verifies)
+                .unwrap_or(self.settings.insecure_skip_verify),
+            root_cas: ssl_proxy.ca_certificate_file.iter().cloned().collect(),
+            certificates: ssl_proxy
+                .machine_certificate_file
+                .iter()
+                .map(|file| Certificate {
+                    cert_file: file.clone(),
+                    key_file: file.clone(),
+                })
+                .collect(),
+            forwarding_timeouts: Some(self.forwarding_timeouts(&BTreeMap::new())),
+        }
+    }
+
+    /// The entry points of the host's routers: the plain http host of a
+    /// site with an https host is left on the redirect entry points, where
+    /// it doesn't shadow the https host's routers.
+    fn entry_points(&self) -> Vec<String> {
+        if self.https_twin {
+            self.settings.redirect_entry_points.clone()
+        } else {
+            self.settings.entry_points.clone()
+        }
+    }
+
+    /// The tls section of the host's routers, none on the redirect entry
+    /// points.
+    fn tls(&self) -> Option<RouterTls> {
+        (!self.https_twin).then(|| self.router_tls())
+    }
+
+    /// The router TLS settings: the profile's `tls_options`, or the ones
+    /// matching the host's protocols and ciphers.
+    fn router_tls(&self) -> RouterTls {
+        let options = self
+            .settings
+            .tls_options
+            .clone()
+            .or_else(|| self.tls_options(&mut vec![]).map(|(name, _)| name));
+        RouterTls {
+            options,
+            cert_resolver: self.settings.cert_resolver.clone(),
+        }
+    }
+
+    /// The `tls.options` of the host's own protocols and ciphers, unless
+    /// the profile names the options to use.
+    fn tls_options(&self, notes: &mut Vec<String>) -> Option<(String, TlsOptions)> {
+        if !self.ssl_config.enabled || self.settings.tls_options.is_some() {
+            return None;
+        }
+        tls::to_tls_options(&self.ssl_config, notes)
+    }
+
+    /// A router answering with its redirect middlewares only. Plain http
+    /// hosts are usually kept around just to redirect, so they are served
+    /// on the redirect entry points (`web`).
+    fn redirect_router(&self, rule: String, priority: i64, middlewares: Vec<String>) -> Router {
+        let (entry_points, tls) = if self.ssl_config.enabled {
+            (&self.settings.entry_points, Some(self.router_tls()))
+        } else {
+            (&self.settings.redirect_entry_points, None)
+        };
+        Router {
+            entry_points: entry_points.clone(),
+            rule,
+            priority: Some(priority),
+            middlewares,
+            service: "noop@internal".to_string(),
+            tls,
+        }
     }
 
     pub fn to_json_config(&self) -> Option<Value> {
-        let name = self.server_name.clone();
-        let name = name.replace("http://", "");
-        let name = name.replace("https://", "");
+        let name = self.name();
         debug!("name: {}", name.len());
         if name.is_empty() {
             return None;
         }
-        // let dashed_str = name.replace(".", "-");
-        let (host, port, url) = self.get_host_and_port();
+        let backends = self
+            .backends()
+            .into_iter()
+            .map(|backend| {
+                json!({
+                    "port": backend.port.parse::<u16>().unwrap_or_default(),
+                    "url": backend.base_url(),
+                    "ip": backend.host,
+                })
+            })
+            .collect::<Vec<_>>();
         Some(json!({
             "name": name,
-            "backends": [
-                {
-                    "port": port.parse::<u16>().unwrap(),
-                    "url": url,
-                    "ip": host,
-                }
-            ],
+            "backends": backends,
             "server_aliases": self.server_aliases,
             "document_root": self.document_root,
             "custom_log": self.custom_log,
+            "locations": self.locations,
+            "middlewares": self.middlewares,
+            "external_auth": self.external_auth(),
         }))
     }
 }
 
+/// Renders key/value pairs as `etcdctl put` shell commands.
+pub fn etcdctl_commands(pairs: &[(String, String)]) -> String {
+    let mut config = String::new();
+    for (key, value) in pairs {
+        let value = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$")
+            .replace('`', "\\`");
+        config.push_str(&format!("etcdctl put {key} \"{value}\"\n"));
+    }
+    config
+}
+
 impl Display for VirtualHost {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", serde_json::to_string(&self).unwrap())
@@ -322,14 +1505,583 @@ impl Display for VirtualHost {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Location {
+    kind: LocationKind,
     path: String,
+    regex: bool,
+    /// Added to the router priority, see [`ProxyPass::precedence`].
+    precedence: i64,
     allow_methods: Vec<String>,
+    deny_methods: Vec<String>,
+    headers: Vec<Header>,
+    proxy_pass: Option<ProxyPass>,
+    access_rules: Vec<AccessRule>,
+    auth: AuthConfig,
+    /// Per-directory rewrites, matched against the path relative to the
+    /// section.
+    rewrite_rules: Vec<RewriteRule>,
+    rewrite_base: Option<String>,
+    redirect: Option<Redirect>,
+}
+
+/// The section a [`Location`] was declared with.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationKind {
+    #[default]
+    Location,
+    Directory,
+    Files,
+}
+
+/// A `ProxyPass` or `ProxyPassMatch` rule.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProxyPass {
+    path: String,
+    url: String,
+    regex: bool,
+    /// `ProxyPass /path !` keeps the path from being proxied.
+    excluded: bool,
+    /// Worker parameters such as `timeout=30` or `retry=0`.
+    params: BTreeMap<String, String>,
+    /// Added to the router priority for nginx's `location =` and `^~`,
+    /// which are matched before regex locations whatever their length.
+    precedence: i64,
+}
+
+impl ProxyPass {
+    /// Builds a rule from its arguments, `path` being `None` when the rule
+    /// sits inside a `<Location>` and takes its path from there.
+    fn from_args(path: Option<String>, args: &[String], regex: bool) -> Self {
+        let mut args = args.iter();
+        let path = path.or_else(|| args.next().cloned()).unwrap_or_default();
+        let url = args.next().cloned().unwrap_or_default();
+        let params = parse_params(args);
+        ProxyPass {
+            path,
+            excluded: url == "!",
+            url,
+            regex,
+            params,
+            precedence: 0,
+        }
+    }
+
+    pub fn upstream(&self) -> Option<Upstream> {
+        if self.excluded {
+            return None;
+        }
+        Upstream::parse(&self.url)
+    }
+
+    fn is_root(&self) -> bool {
+        !self.regex && self.path.trim_end_matches('/').is_empty()
+    }
+
+    /// Router priority: the more specific the path, the earlier it is
+    /// matched. Plain prefixes win over regexes of the same length.
+    fn priority(&self) -> i64 {
+        let priority = if self.is_root() {
+            1
+        } else if self.regex {
+            10 + self.path.len() as i64
+        } else {
+            20 + self.path.len() as i64
+        };
+        priority + self.precedence
+    }
+
+    /// The middleware rewriting the frontend path into the upstream one,
+    /// along with the suffix used to name it.
+    fn path_middleware(&self, upstream: &Upstream) -> Option<(&'static str, Middleware)> {
+        let upstream_path = upstream.path.trim_end_matches('/');
+        if self.regex {
+            if upstream_path.is_empty() {
+                return None;
+            }
+            return if upstream_path.contains('$') {
+                Some((
+                    "replace",
+                    Middleware {
+                        replace_path_regex: Some(ReplacePathRegex {
+                            regex: self.path.clone(),
+                            replacement: go_replacement(&upstream.path, 0),
+                        }),
+                        ..Default::default()
+                    },
+                ))
+            } else {
+                Some((
+                    "replace",
+                    Middleware {
+                        replace_path: Some(ReplacePath {
+                            path: upstream.path.clone(),
+                        }),
+                        ..Default::default()
+                    },
+                ))
+            };
+        }
+
+        let path = self.path.trim_end_matches('/');
+        if path == upstream_path {
+            None
+        } else if upstream_path.is_empty() {
+            Some((
+                "strip",
+                Middleware {
+                    strip_prefix: Some(StripPrefix {
+                        prefixes: vec![path.to_string()],
+                    }),
+                    ..Default::default()
+                },
+            ))
+        } else {
+            Some((
+                "replace",
+                Middleware {
+                    replace_path_regex: Some(ReplacePathRegex {
+                        regex: format!("^{}(.*)", regex_escape(path)),
+                        replacement: format!("{upstream_path}${{1}}"),
+                    }),
+                    ..Default::default()
+                },
+            ))
+        }
+    }
+}
+
+/// Parses `key=value` worker parameters, keys being case insensitive.
+fn parse_params<'a>(args: impl Iterator<Item = &'a String>) -> BTreeMap<String, String> {
+    args.map(|param| match param.split_once('=') {
+        Some((key, value)) => (key.to_lowercase(), value.to_string()),
+        None => (param.to_lowercase(), String::new()),
+    })
+    .collect()
+}
+
+/// `name`, or `name-2`, `name-3`, ... when a router already has it, as
+/// paths such as `/api` and `/api/` have the same slug.
+fn unique_router_name(config: &DynamicConfig, name: String) -> String {
+    if !config.http.routers.contains_key(&name) {
+        return name;
+    }
+    (2..)
+        .map(|index| format!("{name}-{index}"))
+        .find(|candidate| !config.http.routers.contains_key(candidate))
+        .expect("some index is free")
+}
+
+/// Turns a path into something usable in router and service names.
+fn slug(path: &str) -> String {
+    let slug = path
+        .trim_start_matches("(?i)")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>();
+    let slug = slug
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "root".to_string()
+    } else {
+        slug.to_lowercase()
+    }
+}
+
+fn regex_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Converts Apache `$1` backreferences into Go's `${1}`, which can't be
+/// confused with a named group when followed by other characters. `offset`
+/// is added to every group number, for patterns embedded after other groups.
+fn go_replacement(replacement: &str, offset: usize) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' && chars.peek().is_some_and(char::is_ascii_digit) {
+            let mut group = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                group.push(digit);
+            }
+            let group = group.parse::<usize>().unwrap_or_default() + offset;
+            out.push_str(&format!("${{{group}}}"));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// A `ProxyPassReverse` rule.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ProxyPassReverse {
+    path: String,
+    url: String,
+}
+
+/// A `mod_proxy_balancer` cluster, declared with `<Proxy balancer://name>`
+/// or with `BalancerMember balancer://name ...` lines.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Balancer {
+    name: String,
+    members: Vec<BalancerMember>,
+    /// `ProxySet` parameters such as `lbmethod` or `stickysession`.
+    params: BTreeMap<String, String>,
+    /// Declared with nginx's `upstream name {}`, which `proxy_pass` refers
+    /// to as `http://name`.
+    #[serde(default)]
+    upstream: bool,
+}
+
+/// A `BalancerMember` with its parameters, e.g. `loadfactor=2` or
+/// `status=+H`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BalancerMember {
+    url: String,
+    params: BTreeMap<String, String>,
+}
+
+impl Balancer {
+    /// `ProxyPass` parameters that configure the balancer rather than the
+    /// path.
+    const PARAMS: [&'static str; 5] = [
+        "lbmethod",
+        "stickysession",
+        "scolonpathdelim",
+        "nofailover",
+        "maxattempts",
+    ];
+
+    fn named(url: &str) -> Self {
+        Balancer {
+            name: balancer_name(url).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// The Traefik servers for the members taking traffic. Traefik has no
+    /// hot standby, so standby members are left out.
+    fn servers(&self, host: &str) -> Vec<Server> {
+        if let Some(lbmethod) = self.params.get("lbmethod") {
+            if !lbmethod.eq_ignore_ascii_case("byrequests") {
+                warn!(
+                    "{host}: balancer {} uses lbmethod={lbmethod}, traefik balances by weighted round robin",
+                    self.name
+                );
+            }
+        }
+        let mut servers = vec![];
+        for member in &self.members {
+            let status = member.status();
+            if status.contains(&'D') || status.contains(&'S') {
+                debug!("{host}: skipping disabled balancer member {}", member.url);
+                continue;
+            }
+            if status.contains(&'H') || status.contains(&'R') {
+                warn!(
+                    "{host}: balancer member {} is a hot standby, traefik can't express it",
+                    member.url
+                );
+                continue;
+            }
+            let Some(upstream) = Upstream::parse(&member.url) else {
+                warn!("{host}: invalid balancer member {}", member.url);
+                continue;
+            };
+            if !upstream.path.trim_end_matches('/').is_empty() {
+                warn!(
+                    "{host}: the path of balancer member {} is not forwarded by traefik",
+                    member.url
+                );
+            }
+            servers.push(Server {
+                url: upstream.base_url(),
+                weight: member.params.get("loadfactor").and_then(|f| f.parse().ok()),
+            });
+        }
+        servers
+    }
+
+    /// Sticky sessions for balancers with a `stickysession` parameter.
+    /// Apache follows the route suffix of the application's own session
+    /// cookie while traefik pins clients with a cookie of its own, so that
+    /// cookie is named after the balancer instead.
+    fn sticky(&self, secure: bool) -> Option<Sticky> {
+        self.params.get("stickysession")?;
+        Some(Sticky {
+            cookie: Some(StickyCookie {
+                name: format!("{}-route", self.name),
+                secure,
+                http_only: true,
+            }),
+        })
+    }
+
+    /// Adds the members and settings of another declaration of the same
+    /// balancer.
+    fn extend(&mut self, other: Balancer) {
+        self.members.extend(other.members);
+        self.params.extend(other.params);
+    }
+}
+
+impl BalancerMember {
+    fn from_args(args: &[String]) -> Self {
+        let mut args = args.iter();
+        BalancerMember {
+            url: args.next().cloned().unwrap_or_default(),
+            params: parse_params(args),
+        }
+    }
+
+    /// The flags set by the `status` parameter, e.g. `H` for `status=+H`.
+    fn status(&self) -> Vec<char> {
+        let mut flags = vec![];
+        let mut set = true;
+        for c in self
+            .params
+            .get("status")
+            .into_iter()
+            .flat_map(|s| s.chars())
+        {
+            match c {
+                '+' => set = true,
+                '-' => set = false,
+                flag => {
+                    let flag = flag.to_ascii_uppercase();
+                    flags.retain(|f| *f != flag);
+                    if set {
+                        flags.push(flag);
+                    }
+                }
+            }
+        }
+        flags
+    }
+}
+
+/// The name in a `balancer://name/` URL.
+fn balancer_name(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("balancer://")?;
+    Some(rest.split('/').next().unwrap_or_default().to_string())
+}
+
+/// Adds a balancer, extending an earlier declaration with the same name.
+fn merge_balancer(balancers: &mut Vec<Balancer>, balancer: Balancer) {
+    match balancers.iter_mut().find(|b| b.name == balancer.name) {
+        Some(existing) => existing.extend(balancer),
+        None => balancers.push(balancer),
+    }
+}
+
+/// Handles the balancer directives, `<Proxy balancer://name>` along with
+/// `BalancerMember` and `ProxySet` lines naming their balancer. Returns
+/// whether the directive was one of them.
+fn add_balancer_directive(balancers: &mut Vec<Balancer>, directive: &Directive<Apache>) -> bool {
+    let Some(url) = directive.args.first() else {
+        return false;
+    };
+    if balancer_name(url).is_none() {
+        return false;
+    }
+    let mut balancer = Balancer::named(url);
+    match directive.name.as_str() {
+        "Proxy" => {
+            for child in directive.children.iter().flatten() {
+                match child.name.as_str() {
+                    "BalancerMember" => balancer
+                        .members
+                        .push(BalancerMember::from_args(&child.args)),
+                    "ProxySet" => balancer.params.extend(parse_params(child.args.iter())),
+                    _ => {}
+                }
+            }
+        }
+        "BalancerMember" => balancer
+            .members
+            .push(BalancerMember::from_args(&directive.args[1..])),
+        "ProxySet" => balancer
+            .params
+            .extend(parse_params(directive.args[1..].iter())),
+        _ => return false,
+    }
+    merge_balancer(balancers, balancer);
+    true
+}
+
+/// The parts of a backend URL such as `http://10.0.0.1:8080/app`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upstream {
+    pub scheme: String,
+    pub host: String,
+    pub port: String,
+    pub path: String,
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return None;
+        }
+        let default_port = match scheme {
+            "https" | "wss" => "443",
+            _ => "80",
+        };
+        // `[::1]:8080` keeps the brackets in the host
+        let (host, port) = match authority.rfind(':') {
+            Some(index) if !authority[index..].contains(']') => {
+                (&authority[..index], &authority[index + 1..])
+            }
+            _ => (authority, default_port),
+        };
+        Some(Upstream {
+            scheme: scheme.to_lowercase(),
+            host: host.to_string(),
+            port: port.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// `scheme://host`, with the port only when it isn't the scheme's
+    /// default.
+    pub fn base_url(&self) -> String {
+        match (self.scheme.as_str(), self.port.as_str()) {
+            ("http" | "ws", "80") | ("https" | "wss", "443") => {
+                format!("{}://{}", self.scheme, self.host)
+            }
+            _ => format!("{}://{}:{}", self.scheme, self.host, self.port),
+        }
+    }
 }
 
+/// `Timeout`, `ProxyTimeout` and `KeepAliveTimeout` as Go durations.
+/// Unset fields fall back to the server level ones.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct RewriteRule {
-    pattern: String,
-    replacement: String,
+pub struct Timeouts {
+    timeout: Option<String>,
+    proxy_timeout: Option<String>,
+    keep_alive_timeout: Option<String>,
+}
+
+impl Timeouts {
+    /// Records a timeout directive, returning false for any other.
+    fn set(&mut self, name: &str, args: &[String]) -> bool {
+        let field = match name {
+            "Timeout" => &mut self.timeout,
+            "ProxyTimeout" => &mut self.proxy_timeout,
+            "KeepAliveTimeout" => &mut self.keep_alive_timeout,
+            _ => return false,
+        };
+        *field = args.first().and_then(|value| go_duration(value));
+        true
+    }
+
+    fn inherit(&mut self, server: &Timeouts) {
+        let server = server.clone();
+        self.timeout = self.timeout.take().or(server.timeout);
+        self.proxy_timeout = self.proxy_timeout.take().or(server.proxy_timeout);
+        self.keep_alive_timeout = self.keep_alive_timeout.take().or(server.keep_alive_timeout);
+    }
+
+    /// How long a backend may take to answer; `ProxyTimeout` defaults to
+    /// `Timeout`.
+    fn response(&self) -> Option<String> {
+        self.proxy_timeout.clone().or_else(|| self.timeout.clone())
+    }
+}
+
+/// Turns an Apache (or nginx) timeout, in seconds unless suffixed with
+/// `ms`, `s`, `m` or `h`, into a Go duration.
+fn go_duration(value: &str) -> Option<String> {
+    let (number, unit) = ["ms", "s", "m", "h"]
+        .iter()
+        .find_map(|unit| Some((value.strip_suffix(unit)?, *unit)))
+        .unwrap_or((value, "s"));
+    match number.trim().parse::<u64>() {
+        Ok(number) => Some(format!("{number}{unit}")),
+        Err(_) => {
+            warn!("Ignoring timeout {value}, it isn't a number of seconds");
+            None
+        }
+    }
+}
+
+/// How Apache talks TLS to the backends, from the `SSLProxy*` directives.
+/// Unset fields fall back to the server level ones.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SslProxyConfig {
+    enabled: Option<bool>,
+    /// `none`, `optional`, `require` or `optional_no_ca`.
+    verify: Option<String>,
+    check_peer_name: Option<bool>,
+    ca_certificate_file: Option<String>,
+    /// A PEM file holding both the client certificate and its key.
+    machine_certificate_file: Option<String>,
+    /// `ProxyPreserveHost`, which makes the backend certificate be checked
+    /// against the client's Host instead of the backend URL.
+    preserve_host: Option<bool>,
+    /// The name checked on the backend certificate, nginx's `proxy_ssl_name`.
+    server_name: Option<String>,
+}
+
+impl SslProxyConfig {
+    /// Records a `SSLProxy*` directive, returning false for any other.
+    fn set(&mut self, name: &str, args: &[String]) -> bool {
+        let Some(arg) = args.first() else {
+            return false;
+        };
+        let on = arg.eq_ignore_ascii_case("on");
+        match name {
+            "SSLProxyEngine" => self.enabled = Some(on),
+            "SSLProxyVerify" => self.verify = Some(arg.to_lowercase()),
+            "SSLProxyCheckPeerName" => self.check_peer_name = Some(on),
+            "SSLProxyCACertificateFile" => self.ca_certificate_file = Some(arg.to_string()),
+            "SSLProxyMachineCertificateFile" => {
+                self.machine_certificate_file = Some(arg.to_string())
+            }
+            "ProxyPreserveHost" => self.preserve_host = Some(on),
+            _ => return false,
+        }
+        true
+    }
+
+    fn inherit(&mut self, server: &SslProxyConfig) {
+        let server = server.clone();
+        self.enabled = self.enabled.or(server.enabled);
+        self.verify = self.verify.take().or(server.verify);
+        self.check_peer_name = self.check_peer_name.or(server.check_peer_name);
+        self.ca_certificate_file = self
+            .ca_certificate_file
+            .take()
+            .or(server.ca_certificate_file);
+        self.machine_certificate_file = self
+            .machine_certificate_file
+            .take()
+            .or(server.machine_certificate_file);
+        self.preserve_host = self.preserve_host.or(server.preserve_host);
+        self.server_name = self.server_name.take().or(server.server_name);
+    }
+
+    /// Whether backend certificates are verified; `None` when Apache isn't
+    /// told either way.
+    fn verifies(&self) -> Option<bool> {
+        self.verify.as_deref().map(|verify| verify == "require")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -338,11 +2090,146 @@ pub struct SslConfig {
     certificate_file: String,
     key_file: String,
     chain_file: Option<String>,
-    honor_cipher_order: bool,
+    honor_cipher_order: Option<bool>,
     ciphers: Option<String>,
     protocols: Option<Vec<String>>,
 }
 
+impl SslConfig {
+    /// Records a mod_ssl directive, returning false for any other.
+    fn set(&mut self, name: &str, args: &[String]) -> bool {
+        let Some(arg) = args.first() else {
+            return false;
+        };
+        let on = arg.eq_ignore_ascii_case("on");
+        match name {
+            "SSLEngine" => self.enabled = on,
+            "SSLCertificateFile" => self.certificate_file = arg.to_string(),
+            "SSLCertificateKeyFile" => self.key_file = arg.to_string(),
+            "SSLCertificateChainFile" => self.chain_file = Some(arg.to_string()),
+            "SSLHonorCipherOrder" => self.honor_cipher_order = Some(on),
+            "SSLProtocol" => self.protocols = Some(args.to_vec()),
+            // `SSLCipherSuite [protocol] cipher-spec`, where TLS 1.3 suites
+            // are given apart
+            "SSLCipherSuite" => match args {
+                [protocol, _] if protocol.eq_ignore_ascii_case("TLSv1.3") => {}
+                [_, ciphers] | [ciphers] => self.ciphers = Some(ciphers.to_string()),
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Takes the server's certificate, protocols, ciphers and cipher order
+    /// where the host has none; `SSLEngine` stays per host.
+    fn inherit(&mut self, server: &SslConfig) {
+        let server = server.clone();
+        if self.certificate_file.is_empty() {
+            self.certificate_file = server.certificate_file;
+            self.key_file = server.key_file;
+            self.chain_file = self.chain_file.take().or(server.chain_file);
+        }
+        self.honor_cipher_order = self.honor_cipher_order.or(server.honor_cipher_order);
+        self.ciphers = self.ciphers.take().or(server.ciphers);
+        self.protocols = self.protocols.take().or(server.protocols);
+    }
+
+    /// The certificate traefik serves for the host. Traefik reads the whole
+    /// chain from `certFile`, so a `SSLCertificateChainFile` points it at
+    /// the combined file instead; without `SSLCertificateKeyFile` the key
+    /// is in the certificate file.
+    pub fn certificate(&self) -> Option<Certificate> {
+        if !self.enabled || self.certificate_file.is_empty() {
+            return None;
+        }
+        let key_file = match self.key_file.is_empty() {
+            true => &self.certificate_file,
+            false => &self.key_file,
+        };
+        Some(Certificate {
+            cert_file: self
+                .combined_file()
+                .unwrap_or_else(|| self.certificate_file.clone()),
+            key_file: key_file.clone(),
+        })
+    }
+
+    /// The file holding the certificate followed by its chain, next to the
+    /// certificate, when the chain is given apart. It is named after both,
+    /// as hosts may serve the same certificate with different chains.
+    pub fn combined_file(&self) -> Option<String> {
+        let chain_file = self.chain_file.as_ref()?;
+        let stem = |file: &str| {
+            [".crt", ".pem", ".cer"]
+                .iter()
+                .find_map(|extension| file.strip_suffix(extension))
+                .unwrap_or(file)
+                .to_string()
+        };
+        let chain_name = Path::new(chain_file)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Some(format!(
+            "{}-{}-fullchain.pem",
+            stem(&self.certificate_file),
+            stem(&chain_name)
+        ))
+    }
+
+    /// Like httpd, relative paths start at the ServerRoot, or at the file
+    /// the host was read from when there is none.
+    fn resolve_files(&mut self, server_root: Option<&Path>, source: Option<&Path>) {
+        let resolve = |file: &mut String| {
+            let path = Path::new(file.as_str());
+            if file.is_empty() || path.is_absolute() {
+                return;
+            }
+            let resolved = match server_root {
+                Some(server_root) => server_root.join(path),
+                None => match source.and_then(Path::parent) {
+                    Some(dir) => dir.join(path),
+                    None => return,
+                },
+            };
+            *file = resolved.display().to_string();
+        };
+        resolve(&mut self.certificate_file);
+        resolve(&mut self.key_file);
+        if let Some(chain_file) = &mut self.chain_file {
+            resolve(chain_file);
+        }
+    }
+
+    /// Expands an `SSLProtocol` expression such as `all -SSLv3 -TLSv1` into
+    /// the list of protocols it enables.
+    pub fn enabled_protocols(&self) -> Vec<String> {
+        const ALL: [&str; 4] = ["TLSv1", "TLSv1.1", "TLSv1.2", "TLSv1.3"];
+        let mut enabled: Vec<String> = vec![];
+        for token in self.protocols.iter().flatten() {
+            let (remove, protocol) = match token.chars().next() {
+                Some('-') => (true, &token[1..]),
+                Some('+') => (false, &token[1..]),
+                _ => (false, token.as_str()),
+            };
+            let protocols: Vec<String> = if protocol.eq_ignore_ascii_case("all") {
+                ALL.iter().map(|p| p.to_string()).collect()
+            } else {
+                vec![protocol.to_string()]
+            };
+            for protocol in protocols {
+                if remove {
+                    enabled.retain(|p| !p.eq_ignore_ascii_case(&protocol));
+                } else if !enabled.iter().any(|p| p.eq_ignore_ascii_case(&protocol)) {
+                    enabled.push(protocol);
+                }
+            }
+        }
+        enabled
+    }
+}
+
 impl From<Directive<Apache>> for ProxyConfig {
     fn from(directive: Directive<Apache>) -> Self {
         let mut pc = ProxyConfig::default();
@@ -351,6 +2238,12 @@ impl From<Directive<Apache>> for ProxyConfig {
                 let virtual_host = VirtualHost::from(directive);
                 pc.virtual_hosts.push(virtual_host);
             }
+            "Proxy" | "BalancerMember" | "ProxySet"
+                if add_balancer_directive(&mut pc.balancers, &directive) => {}
+            name if pc.ssl_proxy.set(name, &directive.args) => {}
+            name if pc.timeouts.set(name, &directive.args) => {}
+            name if pc.ssl_config.set(name, &directive.args) => {}
+            name if pc.oidc.set(name, &directive.args) => {}
             other => {
                 pc.meta.insert(
                     other.to_string(),
@@ -364,12 +2257,16 @@ impl From<Directive<Apache>> for ProxyConfig {
 
 impl From<Directive<Apache>> for VirtualHost {
     fn from(directive: Directive<Apache>) -> Self {
-        let mut virtual_host = VirtualHost::default();
-        virtual_host.host = directive.args[0].to_string();
-        virtual_host.ssl_config = SslConfig::default();
+        let mut virtual_host = VirtualHost {
+            host: directive.args[0].to_string(),
+            ssl_config: SslConfig::default(),
+            ..Default::default()
+        };
+        // `RewriteCond` lines apply to the next `RewriteRule`
+        let mut conditions = vec![];
         directive.children.iter().for_each(|subchildren| {
             subchildren
-                .into_iter()
+                .iter()
                 .for_each(|subchild| match subchild.name.as_str() {
                     "ServerName" => {
                         virtual_host.server_name = subchild.args[0].to_string();
@@ -382,51 +2279,100 @@ impl From<Directive<Apache>> for VirtualHost {
                             .server_aliases
                             .push(subchild.args[0].to_string());
                     }
-                    "Header" => {
+                    "Header" | "RequestHeader" => {
                         virtual_host
                             .headers
-                            .entry(subchild.args[0].to_string())
-                            .and_modify(|x| x.push(subchild.args[1].to_string()))
-                            .or_insert(vec![]);
-                    }
-                    "RequestHeader" => {
-                        virtual_host
-                            .headers
-                            .entry(subchild.args[0].to_string())
-                            .and_modify(|x| x.push(subchild.args[1].to_string()))
-                            .or_insert(vec![]);
+                            .extend(Header::from_args(&subchild.name, &subchild.args));
                     }
                     "RewriteEngine" => {
-                        virtual_host.rewrite_engine = subchild.args[0].to_string() == "On";
+                        virtual_host.rewrite_engine = subchild.args[0].eq_ignore_ascii_case("on");
                     }
-                    "RewriteRule" | "ProxyPassReverse" | "ProxyPass" => {
-                        virtual_host.rewrite_rules.push(RewriteRule::from(subchild));
+                    "RewriteCond" => {
+                        conditions.extend(RewriteCond::from_args(&subchild.args));
                     }
-                    "CustomLog" => {
-                        virtual_host.custom_log = subchild.args[0].to_string();
+                    "RewriteRule" => {
+                        virtual_host.rewrite_rules.extend(RewriteRule::from_args(
+                            &subchild.args,
+                            std::mem::take(&mut conditions),
+                        ));
                     }
-                    "SSLEngine" => {
-                        virtual_host.ssl_config.enabled =
-                            subchild.args[0].to_string().to_lowercase() == "on";
+                    "Redirect" | "RedirectMatch" | "RedirectPermanent" | "RedirectTemp" => {
+                        virtual_host.redirects.extend(Redirect::from_args(
+                            &subchild.name,
+                            &subchild.args,
+                            None,
+                        ));
                     }
-                    "SSLCertificateFile" => {
-                        virtual_host.ssl_config.certificate_file = subchild.args[0].to_string();
+                    "RewriteMap" => {
+                        virtual_host
+                            .rewrite_maps
+                            .extend(RewriteMap::from_args(&subchild.args));
+                    }
+                    "ProxyPass" | "ProxyPassMatch" => {
+                        virtual_host.proxy_passes.push(ProxyPass::from_args(
+                            None,
+                            &subchild.args,
+                            subchild.name == "ProxyPassMatch",
+                        ));
+                    }
+                    "ProxyPassReverse" => match subchild.args.first() {
+                        Some(path) => {
+                            virtual_host.proxy_pass_reverses.push(ProxyPassReverse {
+                                path: path.to_string(),
+                                url: subchild.args.get(1).cloned().unwrap_or_default(),
+                            });
+                        }
+                        None => warn!("Skipping ProxyPassReverse without a path"),
+                    },
+                    "Location" | "LocationMatch" | "Directory" | "DirectoryMatch" | "Files"
+                    | "FilesMatch" => {
+                        let location = Location::from(subchild);
+                        if let Some(proxy_pass) = &location.proxy_pass {
+                            virtual_host.proxy_passes.push(proxy_pass.clone());
+                        }
+                        if let Some(redirect) = &location.redirect {
+                            virtual_host.redirects.push(redirect.clone());
+                        }
+                        // `ProxyPassReverse` inside a location takes its path
+                        // from there
+                        for child in subchild.children.iter().flatten() {
+                            if child.name == "ProxyPassReverse" {
+                                virtual_host.proxy_pass_reverses.push(ProxyPassReverse {
+                                    path: location.path.clone(),
+                                    url: child.args.first().cloned().unwrap_or_default(),
+                                });
+                            }
+                        }
+                        virtual_host.locations.push(location);
+                    }
+                    "Proxy" | "BalancerMember" | "ProxySet" => {
+                        add_balancer_directive(&mut virtual_host.balancers, subchild);
                     }
-                    "SSLCertificateKeyFile" => {
-                        virtual_host.ssl_config.key_file = subchild.args[0].to_string();
+                    "CustomLog" => {
+                        virtual_host.custom_log = subchild.args[0].to_string();
                     }
-                    "SSLCertificateChainFile" => {
-                        virtual_host.ssl_config.chain_file = Some(subchild.args[0].to_string());
+                    "SSLEngine"
+                    | "SSLCertificateFile"
+                    | "SSLCertificateKeyFile"
+                    | "SSLCertificateChainFile"
+                    | "SSLHonorCipherOrder"
+                    | "SSLCipherSuite"
+                    | "SSLProtocol" => {
+                        virtual_host.ssl_config.set(&subchild.name, &subchild.args);
                     }
-                    "SSLHonorCipherOrder" => {
-                        virtual_host.ssl_config.honor_cipher_order =
-                            subchild.args[0].to_string().to_lowercase() == "on";
+                    "SSLProxyEngine"
+                    | "SSLProxyVerify"
+                    | "SSLProxyCheckPeerName"
+                    | "SSLProxyCACertificateFile"
+                    | "SSLProxyMachineCertificateFile"
+                    | "ProxyPreserveHost" => {
+                        virtual_host.ssl_proxy.set(&subchild.name, &subchild.args);
                     }
-                    "SSLCiphers" => {
-                        virtual_host.ssl_config.ciphers = Some(subchild.args[0].to_string());
+                    "Timeout" | "ProxyTimeout" | "KeepAliveTimeout" => {
+                        virtual_host.timeouts.set(&subchild.name, &subchild.args);
                     }
-                    "SSLProtocols" => {
-                        virtual_host.ssl_config.protocols = Some(subchild.args.clone());
+                    "OIDCProviderMetadataURL" | "OIDCRedirectURI" | "OIDCClientID" => {
+                        virtual_host.oidc.set(&subchild.name, &subchild.args);
                     }
                     "LogLevel" => {
                         virtual_host.log_level = subchild.args[0].to_string();
@@ -438,11 +2384,437 @@ impl From<Directive<Apache>> for VirtualHost {
     }
 }
 
-impl From<&Directive<Apache>> for RewriteRule {
+impl From<&Directive<Apache>> for Location {
     fn from(directive: &Directive<Apache>) -> Self {
-        let mut rewrite_rule = RewriteRule::default();
-        rewrite_rule.pattern = directive.args[0].to_string();
-        rewrite_rule.replacement = directive.args[1].to_string();
-        rewrite_rule
+        let name = directive.name.as_str();
+        let kind = match name {
+            "Directory" | "DirectoryMatch" => LocationKind::Directory,
+            "Files" | "FilesMatch" => LocationKind::Files,
+            _ => LocationKind::Location,
+        };
+        // `<Location ~ "regex">` is the same as `<LocationMatch "regex">`
+        let (regex, path) = match directive.args.as_slice() {
+            [tilde, path, ..] if tilde == "~" => (true, path.to_string()),
+            [path, ..] => (name.ends_with("Match"), path.to_string()),
+            [] => (false, String::new()),
+        };
+        let mut location = Location {
+            kind,
+            path,
+            regex,
+            ..Default::default()
+        };
+
+        let mut conditions = vec![];
+        for child in directive.children.iter().flatten() {
+            match child.name.as_str() {
+                "RewriteCond" => {
+                    conditions.extend(RewriteCond::from_args(&child.args));
+                }
+                "RewriteRule" => {
+                    location.rewrite_rules.extend(RewriteRule::from_args(
+                        &child.args,
+                        std::mem::take(&mut conditions),
+                    ));
+                }
+                "RewriteBase" => {
+                    location.rewrite_base = child.args.first().cloned();
+                }
+                "Redirect" | "RedirectMatch" | "RedirectPermanent" | "RedirectTemp" => {
+                    location.redirect =
+                        Redirect::from_args(&child.name, &child.args, Some(&location.path));
+                }
+                "ProxyPass" | "ProxyPassMatch" => {
+                    location.proxy_pass = Some(ProxyPass::from_args(
+                        Some(location.path.clone()),
+                        &child.args,
+                        location.regex,
+                    ));
+                }
+                "Header" | "RequestHeader" => {
+                    location
+                        .headers
+                        .extend(Header::from_args(&child.name, &child.args));
+                }
+                "Limit" | "LimitExcept" => {
+                    let rule = AccessRule::from(child);
+                    if rule.children.iter().any(AccessRule::denies_all) {
+                        if child.name == "Limit" {
+                            location.deny_methods.extend(child.args.iter().cloned());
+                        } else {
+                            location.allow_methods.extend(child.args.iter().cloned());
+                        }
+                    } else {
+                        location.access_rules.push(rule);
+                    }
+                }
+                name if AccessRule::is_access_directive(name) => {
+                    location.access_rules.push(AccessRule::from(child));
+                }
+                name if location.auth.set(name, &child.args) => {}
+                _ => {}
+            }
+        }
+        location
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use misc_conf::ast::DirectiveTrait;
+
+    use super::*;
+
+    fn proxy_config(apache: &str) -> ProxyConfig {
+        let mut configs = ProxyConfig::default();
+        for directive in Directive::<Apache>::parse(apache.as_bytes()).unwrap() {
+            configs.merge(ProxyConfig::from(directive));
+        }
+        configs.inherit_server_config();
+        configs
+    }
+
+    fn service_urls(config: &DynamicConfig) -> Vec<String> {
+        config
+            .http
+            .services
+            .values()
+            .flat_map(|service| service.load_balancer.servers.iter())
+            .map(|server| server.url.clone())
+            .collect()
+    }
+
+    #[test]
+    fn ports_are_only_named_when_hosts_share_a_name() {
+        let configs = proxy_config(
+            "
+<VirtualHost *:443>
+    ServerName www.example.com
+    SSLEngine on
+    ProxyPass / http://app:3000/
+</VirtualHost>
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyPass / http://app:3000/
+</VirtualHost>
+<VirtualHost *:443>
+    ServerName api.example.com
+    ProxyPass / http://api:3000/
+</VirtualHost>
+",
+        );
+        let names = configs
+            .virtual_hosts
+            .iter()
+            .map(VirtualHost::dashed_name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "www-example-com-443",
+                "www-example-com-80",
+                "api-example-com"
+            ]
+        );
+        let config = configs.to_traefik_config();
+        assert!(config.http.routers.contains_key("api-example-com"));
+        assert_eq!(
+            config.http.routers["www-example-com-80"].entry_points,
+            vec!["web"]
+        );
+    }
+
+    #[test]
+    fn bare_proxy_pass_reverse_is_skipped() {
+        let configs = proxy_config(
+            "
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyPass / http://app:3000/
+    ProxyPassReverse
+</VirtualHost>
+",
+        );
+        assert!(configs.virtual_hosts[0].proxy_pass_reverses.is_empty());
+    }
+
+    #[test]
+    fn exclusions_without_an_address_are_skipped() {
+        let config = proxy_config(
+            "
+<VirtualHost *:443>
+    ServerName www.example.com
+    ProxyPass /static !
+    ProxyPass / http://app:3000/
+</VirtualHost>
+",
+        )
+        .to_traefik_config();
+        assert!(!config.http.routers.contains_key("www-example-com-static"));
+        assert!(config.http.routers.contains_key("www-example-com"));
+        assert_eq!(service_urls(&config), vec!["http://app:3000"]);
+    }
+
+    #[test]
+    fn exclusions_go_to_the_hosts_own_address() {
+        let config = proxy_config(
+            "
+<VirtualHost 10.0.0.5:443>
+    ServerName www.example.com
+    ProxyPass /static !
+    ProxyPass / http://app:3000/
+</VirtualHost>
+",
+        )
+        .to_traefik_config();
+        let router = &config.http.routers["www-example-com-static"];
+        let urls = &config.http.services[&router.service].load_balancer.servers;
+        assert_eq!(urls[0].url, "https://10.0.0.5");
+    }
+
+    #[test]
+    fn untranslatable_access_rules_refuse_every_client() {
+        let config = proxy_config(
+            "
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyPass / http://app:3000/
+    <Location />
+        Order deny,allow
+        Deny from 10.0.0.1
+    </Location>
+</VirtualHost>
+",
+        )
+        .to_traefik_config();
+        let middleware = &config.http.middlewares["www-example-com-ipallowlist"];
+        let allow_list = middleware.ip_allow_list.as_ref().unwrap();
+        assert_eq!(allow_list.source_range, vec!["0.0.0.0/32"]);
+        assert!(config.http.routers["www-example-com"]
+            .middlewares
+            .contains(&"www-example-com-ipallowlist".to_string()));
+    }
+
+    #[test]
+    fn logins_nobody_can_pass_refuse_every_client() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("htpasswd"),
+            "alice:$2b$05$tlHGcfIcN1uQWvMYI6s3wOl5m.RqeNbC3D0E4MI6jX5kmiLQv5zbu\n",
+        )
+        .unwrap();
+        let mut configs = proxy_config(
+            "
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyPass / http://app:3000/
+    <Location />
+        AuthType Basic
+        AuthUserFile htpasswd
+        Require user bob
+    </Location>
+</VirtualHost>
+",
+        );
+        configs.load_user_files(Some(dir.path()));
+        let config = configs.to_traefik_config();
+        assert!(!config
+            .http
+            .middlewares
+            .contains_key("www-example-com-basicauth"));
+        let middleware = &config.http.middlewares["www-example-com-ipallowlist"];
+        let allow_list = middleware.ip_allow_list.as_ref().unwrap();
+        assert_eq!(allow_list.source_range, vec!["0.0.0.0/32"]);
+        let redacted = configs.redacted();
+        let auth = &redacted.virtual_hosts[0].locations[0].auth;
+        assert_eq!(
+            auth.users.as_deref(),
+            Some(&["alice:<redacted>".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn combined_files_are_named_after_the_certificate_and_chain() {
+        let cases = [
+            (
+                "/etc/ssl/www.crt",
+                Some("/etc/ssl/chain.pem"),
+                Some("/etc/ssl/www-chain-fullchain.pem"),
+            ),
+            (
+                "/etc/ssl/www.pem",
+                Some("ca-bundle.crt"),
+                Some("/etc/ssl/www-ca-bundle-fullchain.pem"),
+            ),
+            (
+                "www",
+                Some("/etc/ssl/intermediate"),
+                Some("www-intermediate-fullchain.pem"),
+            ),
+            ("/etc/ssl/www.crt", None, None),
+        ];
+        for (certificate_file, chain_file, expected) in cases {
+            let ssl_config = SslConfig {
+                certificate_file: certificate_file.to_string(),
+                chain_file: chain_file.map(str::to_string),
+                ..Default::default()
+            };
+            assert_eq!(
+                ssl_config.combined_file().as_deref(),
+                expected,
+                "{certificate_file}"
+            );
+        }
+    }
+
+    fn chained_hosts(dir: &Path, chains: &[&str]) -> ProxyConfig {
+        let hosts = chains
+            .iter()
+            .enumerate()
+            .map(|(i, chain)| {
+                format!(
+                    "
+<VirtualHost *:443>
+    ServerName host{i}.example.com
+    SSLEngine on
+    SSLCertificateFile certs/www.crt
+    SSLCertificateKeyFile certs/www.key
+    SSLCertificateChainFile {chain}
+    ProxyPass / http://app:3000/
+</VirtualHost>
+"
+                )
+            })
+            .collect::<String>();
+        let mut configs = proxy_config(&hosts);
+        configs.resolve_certificate_files(Some(dir));
+        configs
+    }
+
+    #[test]
+    fn certificate_chains() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("certs")).unwrap();
+        std::fs::write(dir.path().join("certs/www.crt"), "CERT").unwrap();
+        std::fs::write(dir.path().join("certs/chain.pem"), "CHAIN\n").unwrap();
+        let configs = chained_hosts(dir.path(), &["certs/chain.pem", "certs/chain.pem"]);
+        let combined = dir.path().join("certs/www-chain-fullchain.pem");
+
+        // a dry run only reports the missing file
+        configs.write_certificate_chains(false).unwrap();
+        assert!(!combined.exists());
+
+        configs.write_certificate_chains(true).unwrap();
+        assert_eq!(std::fs::read_to_string(&combined).unwrap(), "CERT\nCHAIN\n");
+        let ssl_config = &configs.virtual_hosts[0].ssl_config;
+        assert_eq!(
+            ssl_config.combined_file(),
+            Some(combined.display().to_string())
+        );
+    }
+
+    #[test]
+    fn certificate_chains_sharing_a_name_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let configs = chained_hosts(dir.path(), &["a/chain.pem", "b/chain.pem"]);
+        let err = configs.write_certificate_chains(false).unwrap_err();
+        assert!(err.to_string().contains("would combine both"), "{err}");
+    }
+
+    #[test]
+    fn go_durations() {
+        let cases = [
+            ("30", Some("30s")),
+            ("30s", Some("30s")),
+            ("500ms", Some("500ms")),
+            ("5m", Some("5m")),
+            ("2h", Some("2h")),
+            (" 45 ", Some("45s")),
+            ("1.5", None),
+            ("5d", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(go_duration(value).as_deref(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn services_are_split_by_transport() {
+        let config = proxy_config(
+            "
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyTimeout 60
+    ProxyPass /slow/ http://app:3000/ timeout=300
+    ProxyPass /api/ http://app:3000/
+    ProxyPass /v2/ http://app:3000/v2/
+    ProxyPass / http://app:3000/ connectiontimeout=5 ttl=2m
+</VirtualHost>
+",
+        )
+        .to_traefik_config();
+        let routes = [
+            "www-example-com-slow",
+            "www-example-com-api",
+            "www-example-com-v2",
+            "www-example-com",
+        ]
+        .iter()
+        .map(|router| {
+            let service = &config.http.routers[*router].service;
+            let transport = config.http.services[service]
+                .load_balancer
+                .servers_transport
+                .clone()
+                .unwrap();
+            (service.as_str(), transport)
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            routes,
+            vec![
+                (
+                    "www-example-com-slow",
+                    "www-example-com-slow-transport".to_string()
+                ),
+                (
+                    "www-example-com-api",
+                    "www-example-com-transport".to_string()
+                ),
+                (
+                    "www-example-com-api",
+                    "www-example-com-transport".to_string()
+                ),
+                (
+                    "www-example-com",
+                    "www-example-com-root-transport".to_string()
+                ),
+            ]
+        );
+        let timeouts = |transport: &str| {
+            let timeouts = config.http.servers_transports[transport]
+                .forwarding_timeouts
+                .clone()
+                .unwrap();
+            (
+                timeouts.dial_timeout,
+                timeouts.response_header_timeout,
+                timeouts.idle_conn_timeout,
+            )
+        };
+        let some = |value: &str| Some(value.to_string());
+        assert_eq!(
+            timeouts("www-example-com-transport"),
+            (some("60s"), some("60s"), some("30s"))
+        );
+        assert_eq!(
+            timeouts("www-example-com-slow-transport"),
+            (some("300s"), some("300s"), some("30s"))
+        );
+        assert_eq!(
+            timeouts("www-example-com-root-transport"),
+            (some("5s"), some("60s"), some("2m"))
+        );
     }
 }
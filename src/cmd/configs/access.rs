@@ -0,0 +1,631 @@
+//! Access control: mod_authz_core's `Require` and its `<RequireAny>`,
+//! `<RequireAll>` and `<RequireNone>` containers, and mod_access_compat's
+//! `Order`, `Allow`, `Deny` and `Satisfy`.
+//!
+//! Traefik only knows allow lists of client addresses, so the rules of a
+//! section are reduced to the addresses they let through and the login
+//! they ask for, which is left to the auth middlewares. Deny lists and
+//! negations are reported.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use misc_conf::{apache::Apache, ast::Directive};
+use serde::{Deserialize, Serialize};
+
+use super::traefik::{IpAllowList, Middleware};
+
+/// Traefik refuses empty allow lists, and no client connects from the
+/// unspecified address.
+const NOBODY: &str = "0.0.0.0/32";
+
+/// An access control directive (`Require`, `Allow`, `Deny`, `Order`, ...)
+/// or a container grouping them, such as `<RequireAny>` or `<Limit>`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AccessRule {
+    pub(super) directive: String,
+    pub(super) args: Vec<String>,
+    pub(super) children: Vec<AccessRule>,
+}
+
+impl AccessRule {
+    const DIRECTIVES: [&'static str; 10] = [
+        "Require",
+        "RequireAny",
+        "RequireAll",
+        "RequireNone",
+        "Order",
+        "Allow",
+        "Deny",
+        "Satisfy",
+        "Limit",
+        "LimitExcept",
+    ];
+
+    pub(super) fn is_access_directive(name: &str) -> bool {
+        Self::DIRECTIVES
+            .iter()
+            .any(|directive| directive.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the rule refuses every request, e.g. `Require all denied`.
+    pub(super) fn denies_all(&self) -> bool {
+        let args = self.args.join(" ").to_lowercase();
+        match self.directive.to_lowercase().as_str() {
+            "require" => args == "all denied",
+            "deny" => args == "from all",
+            _ => false,
+        }
+    }
+
+    fn is(&self, directive: &str) -> bool {
+        self.directive.eq_ignore_ascii_case(directive)
+    }
+
+    /// Whether the rule takes part in mod_authz_core's evaluation, which
+    /// replaces the rules of enclosing sections.
+    pub(super) fn is_authz(&self) -> bool {
+        ["Require", "RequireAny", "RequireAll", "RequireNone"]
+            .iter()
+            .any(|directive| self.is(directive))
+    }
+}
+
+impl From<&Directive<Apache>> for AccessRule {
+    fn from(directive: &Directive<Apache>) -> Self {
+        AccessRule {
+            directive: directive.name.to_string(),
+            args: directive.args.clone(),
+            children: directive
+                .children
+                .iter()
+                .flatten()
+                .filter(|child| AccessRule::is_access_directive(&child.name))
+                .map(AccessRule::from)
+                .collect(),
+        }
+    }
+}
+
+/// The clients a section lets through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Access {
+    Granted,
+    Denied,
+    /// IPs and CIDR ranges, as traefik's `sourceRange` takes them.
+    Sources(Vec<String>),
+}
+
+/// Who has to log in to pass a section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Login {
+    /// `Require valid-user`
+    AnyUser,
+    /// `Require user ...`
+    Users(Vec<String>),
+    /// Requirements checked against the auth provider, such as `Require
+    /// group` or `Require ldap-attribute`.
+    Other(String),
+}
+
+impl std::fmt::Display for Login {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Login::AnyUser => write!(f, "Require valid-user"),
+            Login::Users(users) => write!(f, "Require user {}", users.join(" ")),
+            Login::Other(requirement) => write!(f, "{requirement}"),
+        }
+    }
+}
+
+impl Login {
+    /// Either of two requirements.
+    fn or(self, other: Login) -> Login {
+        match (self, other) {
+            (Login::AnyUser, _) | (_, Login::AnyUser) => Login::AnyUser,
+            (Login::Users(mut users), Login::Users(others)) => {
+                union(&mut users, others);
+                Login::Users(users)
+            }
+            (a, b) => Login::Other(format!("{a} or {b}")),
+        }
+    }
+
+    /// Both of two requirements.
+    fn and(self, other: Login) -> Login {
+        match (self, other) {
+            (Login::AnyUser, login) | (login, Login::AnyUser) => login,
+            (Login::Users(users), Login::Users(others)) => {
+                Login::Users(users.into_iter().filter(|u| others.contains(u)).collect())
+            }
+            (a, b) => Login::Other(format!("{a} and {b}")),
+        }
+    }
+}
+
+/// What a section asks of a request: a client address, and a login when
+/// `login` is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Requirements {
+    pub(super) access: Access,
+    pub(super) login: Option<Login>,
+}
+
+impl Requirements {
+    const GRANTED: Requirements = Requirements {
+        access: Access::Granted,
+        login: None,
+    };
+
+    pub(super) fn access(access: Access) -> Self {
+        Requirements {
+            access,
+            login: None,
+        }
+    }
+
+    fn login(login: Login) -> Self {
+        Requirements {
+            access: Access::Granted,
+            login: Some(login),
+        }
+    }
+}
+
+/// The rules of a section reduced to the clients they let through and the
+/// login they ask for, or why traefik can't express them.
+pub(super) fn evaluate(rules: &[AccessRule]) -> Result<Requirements, String> {
+    if let Some(rule) = rules
+        .iter()
+        .find(|rule| rule.is("Limit") || rule.is("LimitExcept"))
+    {
+        return Err(format!(
+            "<{} {}> only applies to some methods",
+            rule.directive,
+            rule.args.join(" ")
+        ));
+    }
+    // several `Require` are implicitly any of them
+    let authz = rules
+        .iter()
+        .filter(|rule| rule.is_authz())
+        .map(require)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut terms = vec![];
+    if !authz.is_empty() {
+        terms.push(any(authz)?);
+    }
+    if let Some(term) = compat(rules)? {
+        terms.push(term);
+    }
+    let satisfy_any = rules
+        .iter()
+        .filter(|rule| rule.is("Satisfy"))
+        .any(|rule| rule.args.join(" ").eq_ignore_ascii_case("any"));
+    if satisfy_any && !terms.is_empty() {
+        any(terms)
+    } else {
+        all(terms)
+    }
+}
+
+/// The `ipAllowList` middleware letting the clients through, or none when
+/// every client is.
+pub(super) fn to_middleware(access: &Access) -> Option<Middleware> {
+    let source_range = match access {
+        Access::Granted => return None,
+        Access::Denied => vec![NOBODY.to_string()],
+        Access::Sources(sources) => sources.clone(),
+    };
+    Some(Middleware {
+        ip_allow_list: Some(IpAllowList { source_range }),
+        ..Default::default()
+    })
+}
+
+/// A `Require` directive or one of its containers.
+fn require(rule: &AccessRule) -> Result<Requirements, String> {
+    let children = || {
+        rule.children
+            .iter()
+            .filter(|child| child.is_authz())
+            .map(require)
+            .collect::<Result<Vec<_>, _>>()
+    };
+    match rule.directive.to_lowercase().as_str() {
+        "requireany" => any(children()?),
+        "requireall" => all(children()?),
+        "requirenone" => Err("<RequireNone> is a deny list".to_string()),
+        _ => require_entity(&rule.args),
+    }
+}
+
+/// `Require [not] entity-name [entity-name] ...`
+fn require_entity(args: &[String]) -> Result<Requirements, String> {
+    let directive = format!("Require {}", args.join(" "));
+    let Some((entity, values)) = args.split_first() else {
+        return Err(format!("{directive} has no entity"));
+    };
+    let requirements = match entity.to_lowercase().as_str() {
+        "not" => return Err(format!("{directive} is a deny list")),
+        "all" => match values.first().map(|v| v.to_lowercase()).as_deref() {
+            Some("granted") => Requirements::GRANTED,
+            Some("denied") => Requirements::access(Access::Denied),
+            _ => return Err(format!("{directive} isn't granted or denied")),
+        },
+        "ip" => Requirements::access(Access::Sources(source_ranges(values)?)),
+        "local" => Requirements::access(Access::Sources(vec![
+            "127.0.0.0/8".to_string(),
+            "::1/128".to_string(),
+        ])),
+        "host" | "forward-dns" => {
+            return Err(format!("{directive}: clients can't be matched by name"))
+        }
+        "env" | "expr" | "method" => return Err(format!("{directive} only exists inside httpd")),
+        "valid-user" => Requirements::login(Login::AnyUser),
+        "user" => Requirements::login(Login::Users(values.to_vec())),
+        _ => Requirements::login(Login::Other(directive)),
+    };
+    Ok(requirements)
+}
+
+/// Any of the terms, as in `<RequireAny>` and `Satisfy any`.
+fn any(mut terms: Vec<Requirements>) -> Result<Requirements, String> {
+    if terms.len() == 1 {
+        return Ok(terms.remove(0));
+    }
+    if terms.contains(&Requirements::GRANTED) {
+        return Ok(Requirements::GRANTED);
+    }
+    let mut sources = vec![];
+    let mut login: Option<Login> = None;
+    for term in terms {
+        match term {
+            Requirements {
+                access: Access::Denied,
+                ..
+            } => {}
+            Requirements {
+                access: Access::Sources(ranges),
+                login: None,
+            } => union(&mut sources, ranges),
+            Requirements {
+                access: Access::Granted,
+                login: Some(other),
+            } => {
+                login = Some(match login {
+                    Some(login) => login.or(other),
+                    None => other,
+                });
+            }
+            Requirements {
+                access: Access::Sources(ranges),
+                login: Some(other),
+            } => {
+                return Err(format!(
+                    "clients from {} with {other} can't be one of several choices",
+                    ranges.join(", ")
+                ))
+            }
+            Requirements {
+                access: Access::Granted,
+                login: None,
+            } => unreachable!("granted terms are handled above"),
+        }
+    }
+    match (sources.is_empty(), login) {
+        (true, None) => Ok(Requirements::access(Access::Denied)),
+        (false, None) => Ok(Requirements::access(Access::Sources(sources))),
+        (true, Some(login)) => Ok(Requirements::login(login)),
+        (false, Some(login)) => Err(format!(
+            "clients from {} or with {login} can't be expressed, traefik requires every middleware to pass",
+            sources.join(", ")
+        )),
+    }
+}
+
+/// All of the terms, as in `<RequireAll>` and `Satisfy all`.
+fn all(terms: Vec<Requirements>) -> Result<Requirements, String> {
+    let mut access = Access::Granted;
+    let mut login: Option<Login> = None;
+    for term in terms {
+        match (&access, term.access) {
+            (_, Access::Denied) => return Ok(Requirements::access(Access::Denied)),
+            (_, Access::Granted) => {}
+            (Access::Sources(previous), Access::Sources(ranges)) => {
+                if !same_ranges(previous, &ranges) {
+                    return Err(format!(
+                        "clients from both {} and {} can't be expressed, ranges aren't intersected",
+                        previous.join(", "),
+                        ranges.join(", ")
+                    ));
+                }
+            }
+            (_, ranges) => access = ranges,
+        }
+        if let Some(other) = term.login {
+            login = Some(match login {
+                Some(login) => login.and(other),
+                None => other,
+            });
+        }
+    }
+    Ok(Requirements { access, login })
+}
+
+/// The clients listed by `Allow from` or `Deny from`.
+#[derive(Debug, Default)]
+struct Clients {
+    all: bool,
+    sources: Vec<String>,
+}
+
+impl Clients {
+    fn is_empty(&self) -> bool {
+        !self.all && self.sources.is_empty()
+    }
+
+    fn access(self) -> Access {
+        if self.all {
+            Access::Granted
+        } else if self.sources.is_empty() {
+            Access::Denied
+        } else {
+            Access::Sources(self.sources)
+        }
+    }
+}
+
+/// mod_access_compat's `Order`, `Allow from` and `Deny from`, or none when
+/// the section doesn't use them.
+fn compat(rules: &[AccessRule]) -> Result<Option<Requirements>, String> {
+    let mut order = None;
+    let mut allow = Clients::default();
+    let mut deny = Clients::default();
+    for rule in rules {
+        let target = match rule.directive.to_lowercase().as_str() {
+            "order" => {
+                order = Some(rule.args.join("").to_lowercase());
+                continue;
+            }
+            "allow" => &mut allow,
+            "deny" => &mut deny,
+            _ => continue,
+        };
+        // `from` is optional in practice
+        let args = match rule.args.split_first() {
+            Some((from, args)) if from.eq_ignore_ascii_case("from") => args,
+            _ => &rule.args[..],
+        };
+        if args.iter().any(|arg| arg.eq_ignore_ascii_case("all")) {
+            target.all = true;
+        } else if let Some(arg) = args.iter().find(|arg| arg.starts_with("env=")) {
+            return Err(format!(
+                "{} from {arg} only exists inside httpd",
+                rule.directive
+            ));
+        } else {
+            union(&mut target.sources, source_ranges(args)?);
+        }
+    }
+    let Some(order) = order else {
+        if allow.is_empty() && deny.is_empty() {
+            return Ok(None);
+        }
+        // `Order deny,allow` is the default
+        return deny_allow(allow, deny).map(Some);
+    };
+    match order.as_str() {
+        "deny,allow" => deny_allow(allow, deny).map(Some),
+        "allow,deny" | "mutual-failure" => {
+            // denied unless allowed and not denied
+            if deny.all || allow.is_empty() {
+                Ok(Some(Requirements::access(Access::Denied)))
+            } else if deny.is_empty() {
+                Ok(Some(Requirements::access(allow.access())))
+            } else {
+                Err(format!(
+                    "Deny from {} is a deny list",
+                    deny.sources.join(" ")
+                ))
+            }
+        }
+        _ => Err(format!("Order {order} isn't known")),
+    }
+}
+
+/// `Order deny,allow`: allowed unless denied and not allowed.
+fn deny_allow(allow: Clients, deny: Clients) -> Result<Requirements, String> {
+    if deny.is_empty() || allow.all {
+        Ok(Requirements::access(Access::Granted))
+    } else if deny.all {
+        Ok(Requirements::access(allow.access()))
+    } else {
+        Err(format!(
+            "Deny from {} is a deny list",
+            deny.sources.join(" ")
+        ))
+    }
+}
+
+fn union(sources: &mut Vec<String>, ranges: Vec<String>) {
+    for range in ranges {
+        if !sources.contains(&range) {
+            sources.push(range);
+        }
+    }
+}
+
+fn same_ranges(a: &[String], b: &[String]) -> bool {
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+    a.sort();
+    b.sort();
+    a == b
+}
+
+fn source_ranges(values: &[String]) -> Result<Vec<String>, String> {
+    if values.is_empty() {
+        return Err("no address is given".to_string());
+    }
+    values
+        .iter()
+        .map(|value| source_range(value).ok_or_else(|| format!("{value} isn't an address")))
+        .collect()
+}
+
+/// The IP or CIDR range traefik expects for an address as Apache takes
+/// it: `10.1.2.3`, `10.1` (whole octets), `10.1.0.0/16`,
+/// `10.1.0.0/255.255.0.0` or an IPv6 address or range.
+fn source_range(value: &str) -> Option<String> {
+    if let Some((address, mask)) = value.split_once('/') {
+        let address: IpAddr = address.parse().ok()?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let bits = match mask.parse::<u32>() {
+            Ok(bits) => bits,
+            Err(_) => {
+                let mask = u32::from(mask.parse::<Ipv4Addr>().ok()?);
+                if mask.leading_ones() + mask.trailing_zeros() != 32 {
+                    return None;
+                }
+                mask.leading_ones()
+            }
+        };
+        return (bits <= max).then(|| format!("{address}/{bits}"));
+    }
+    if value.parse::<IpAddr>().is_ok() {
+        return Some(value.to_string());
+    }
+    let octets = value
+        .trim_end_matches('.')
+        .split('.')
+        .map(|octet| octet.parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if octets.len() > 3 {
+        return None;
+    }
+    let mut address = [0u8; 4];
+    address[..octets.len()].copy_from_slice(&octets);
+    Some(format!("{}/{}", Ipv4Addr::from(address), octets.len() * 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use misc_conf::ast::DirectiveTrait;
+
+    use super::*;
+
+    fn rules(apache: &str) -> Vec<AccessRule> {
+        Directive::<Apache>::parse(apache.as_bytes())
+            .unwrap()
+            .iter()
+            .map(AccessRule::from)
+            .collect()
+    }
+
+    fn sources(ranges: &[&str]) -> Option<Access> {
+        Some(Access::Sources(
+            ranges.iter().map(|range| range.to_string()).collect(),
+        ))
+    }
+
+    #[test]
+    fn orders() {
+        let cases = [
+            // deny,allow: allowed unless denied and not allowed
+            (
+                "Order deny,allow\nDeny from all\nAllow from 10.0.0.0/8",
+                sources(&["10.0.0.0/8"]),
+            ),
+            ("Order Deny,Allow\nAllow from 10.1", Some(Access::Granted)),
+            ("Order deny,allow\nDeny from all", Some(Access::Denied)),
+            ("Order deny,allow\nDeny from 10.0.0.1", None),
+            // allow,deny: denied unless allowed and not denied
+            (
+                "Order allow,deny\nAllow from 192.168.1.0/255.255.255.0",
+                sources(&["192.168.1.0/24"]),
+            ),
+            ("Order allow,deny\nAllow from all", Some(Access::Granted)),
+            ("Order allow,deny", Some(Access::Denied)),
+            (
+                "Order allow,deny\nAllow from all\nDeny from all",
+                Some(Access::Denied),
+            ),
+            ("Order allow,deny\nAllow from all\nDeny from 10.0.0.1", None),
+            (
+                "Order mutual-failure\nAllow 10.0.0.1",
+                sources(&["10.0.0.1"]),
+            ),
+            // without an `Order`, deny,allow applies
+            (
+                "Deny from all\nAllow from 127.0.0.1 ::1",
+                sources(&["127.0.0.1", "::1"]),
+            ),
+            ("Order allow,mutual", None),
+            ("Order deny,allow\nAllow from env=internal", None),
+        ];
+        for (config, expected) in cases {
+            let requirements = evaluate(&rules(config));
+            assert_eq!(
+                requirements.ok().map(|requirements| requirements.access),
+                expected,
+                "{config}"
+            );
+        }
+    }
+
+    #[test]
+    fn requires() {
+        let cases = [
+            ("Require all granted", Some(Access::Granted)),
+            ("Require all denied", Some(Access::Denied)),
+            (
+                "Require ip 10.1 172.16.0.0/12",
+                sources(&["10.1.0.0/16", "172.16.0.0/12"]),
+            ),
+            ("Require local", sources(&["127.0.0.0/8", "::1/128"])),
+            (
+                "<RequireAny>\nRequire ip 10.0.0.1\nRequire ip 10.0.0.2\n</RequireAny>",
+                sources(&["10.0.0.1", "10.0.0.2"]),
+            ),
+            (
+                "<RequireAll>\nRequire ip 10.0.0.1\nRequire all denied\n</RequireAll>",
+                Some(Access::Denied),
+            ),
+            ("Require not ip 10.0.0.1", None),
+            ("Require host example.com", None),
+            ("<RequireNone>\nRequire ip 10.0.0.1\n</RequireNone>", None),
+            ("<Limit POST>\nRequire all denied\n</Limit>", None),
+            ("Require ip example", None),
+        ];
+        for (config, expected) in cases {
+            let requirements = evaluate(&rules(config));
+            assert_eq!(
+                requirements.ok().map(|requirements| requirements.access),
+                expected,
+                "{config}"
+            );
+        }
+    }
+
+    #[test]
+    fn satisfy() {
+        let any = evaluate(&rules(
+            "Require valid-user\nOrder allow,deny\nAllow from all\nSatisfy any",
+        ))
+        .unwrap();
+        assert_eq!(any, Requirements::GRANTED);
+        let all = evaluate(&rules(
+            "Require user alice\nOrder allow,deny\nAllow from 10.0.0.1",
+        ))
+        .unwrap();
+        assert_eq!(all.access, sources(&["10.0.0.1"]).unwrap());
+        assert_eq!(all.login, Some(Login::Users(vec!["alice".to_string()])));
+    }
+
+    #[test]
+    fn denied_access_refuses_everybody() {
+        let middleware = to_middleware(&Access::Denied).unwrap();
+        assert_eq!(middleware.ip_allow_list.unwrap().source_range, vec![NOBODY]);
+        assert!(to_middleware(&Access::Granted).is_none());
+    }
+}
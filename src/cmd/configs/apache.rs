@@ -0,0 +1,622 @@
+//! Preprocessing of Apache directive trees before they are converted into
+//! the [`ProxyConfig`](super::ProxyConfig) model.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use misc_conf::{
+    apache::Apache,
+    ast::{Directive, DirectiveTrait},
+};
+
+use crate::error::{ParserError, ParserResult};
+
+/// Include nesting deeper than this is refused.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Modules compiled into every httpd.
+const BUILTIN_MODULES: [&str; 3] = ["core.c", "http_core.c", "mod_so.c"];
+
+/// Modules assumed to be loaded when single files are parsed without the
+/// `LoadModule` lines of httpd.conf, so that the usual `<IfModule mod_ssl.c>`
+/// wrappers are not dropped.
+const COMMON_MODULES: [&str; 10] = [
+    "mod_ssl.c",
+    "mod_rewrite.c",
+    "mod_proxy.c",
+    "mod_proxy_http.c",
+    "mod_proxy_wstunnel.c",
+    "mod_proxy_balancer.c",
+    "mod_headers.c",
+    "mod_auth_basic.c",
+    "mod_authz_core.c",
+    "mod_authz_host.c",
+];
+
+/// The loaded modules and defines used to evaluate `<IfModule>`,
+/// `<IfDefine>` and `${VAR}` references.
+#[derive(Debug, Clone)]
+pub struct ApacheContext {
+    modules: HashSet<String>,
+    defines: HashMap<String, String>,
+}
+
+impl Default for ApacheContext {
+    fn default() -> Self {
+        Self::new(&[], &[])
+    }
+}
+
+/// What a single directive turns into once conditionals are evaluated.
+enum Preprocessed {
+    Keep(Directive<Apache>),
+    Splice(Vec<Directive<Apache>>),
+    Drop,
+}
+
+impl ApacheContext {
+    /// `modules` accepts both identifiers (`ssl_module`) and source file
+    /// names (`mod_ssl.c`); `defines` are given as `NAME` or `NAME=value`,
+    /// like httpd's `-D` flag.
+    pub fn new(modules: &[String], defines: &[String]) -> Self {
+        let mut context = Self {
+            modules: HashSet::new(),
+            defines: HashMap::new(),
+        };
+        for module in BUILTIN_MODULES
+            .iter()
+            .map(|m| m.to_string())
+            .chain(modules.iter().cloned())
+        {
+            context.add_module(&module);
+        }
+        for define in defines {
+            let (name, value) = define.split_once('=').unwrap_or((define, ""));
+            context.defines.insert(name.to_string(), value.to_string());
+        }
+        context
+    }
+
+    /// Also treats the [`COMMON_MODULES`] as loaded, for files parsed
+    /// without their httpd.conf.
+    pub fn with_common_modules(mut self) -> Self {
+        for module in COMMON_MODULES {
+            self.add_module(module);
+        }
+        self
+    }
+
+    /// Registers a module under both of the names `<IfModule>` accepts.
+    fn add_module(&mut self, module: &str) {
+        if let Some(stem) = module.strip_suffix(".c") {
+            if let Some(name) = stem.strip_prefix("mod_") {
+                self.modules.insert(format!("{name}_module"));
+            }
+        } else if let Some(name) = module.strip_suffix("_module") {
+            self.modules.insert(format!("mod_{name}.c"));
+        }
+        self.modules.insert(module.to_string());
+    }
+
+    /// Evaluates conditionals and substitutes variables in `directives`
+    /// without following includes.
+    pub fn evaluate(&mut self, directives: Vec<Directive<Apache>>) -> Vec<Directive<Apache>> {
+        let mut out = vec![];
+        for directive in directives {
+            match self.preprocess(directive) {
+                Preprocessed::Keep(mut directive) => {
+                    if let Some(children) = directive.children.take() {
+                        directive.children = Some(self.evaluate(children));
+                    }
+                    out.push(directive);
+                }
+                Preprocessed::Splice(children) => out.extend(self.evaluate(children)),
+                Preprocessed::Drop => {}
+            }
+        }
+        out
+    }
+
+    fn preprocess(&mut self, mut directive: Directive<Apache>) -> Preprocessed {
+        directive.args = directive
+            .args
+            .iter()
+            .map(|arg| self.substitute(arg))
+            .collect();
+
+        let arg = directive.args.first().cloned().unwrap_or_default();
+        let (negated, arg) = match arg.strip_prefix('!') {
+            Some(arg) => (true, arg.to_string()),
+            None => (false, arg),
+        };
+        match directive.name.to_lowercase().as_str() {
+            "define" => {
+                let value = directive.args.get(1).cloned().unwrap_or_default();
+                self.defines.insert(arg, value);
+                Preprocessed::Drop
+            }
+            "undefine" => {
+                self.defines.remove(&arg);
+                Preprocessed::Drop
+            }
+            "loadmodule" => {
+                self.add_module(&arg);
+                if let Some(file) = directive.args.get(1) {
+                    let stem = Path::new(file).file_stem().unwrap_or_default();
+                    self.add_module(&format!("{}.c", stem.to_string_lossy()));
+                }
+                Preprocessed::Keep(directive)
+            }
+            "ifmodule" | "ifdefine" if directive.children.is_some() => {
+                let present = if directive.name.eq_ignore_ascii_case("ifmodule") {
+                    self.modules.contains(&arg)
+                } else {
+                    self.defines.contains_key(&arg)
+                };
+                if present != negated {
+                    Preprocessed::Splice(directive.children.unwrap_or_default())
+                } else {
+                    debug!("Skipping <{} {}>", directive.name, directive.args.join(" "));
+                    Preprocessed::Drop
+                }
+            }
+            _ => Preprocessed::Keep(directive),
+        }
+    }
+
+    /// Replaces `${VAR}` with the value of a `Define` or, like httpd, of an
+    /// environment variable. Unknown variables are left untouched.
+    fn substitute(&self, arg: &str) -> String {
+        let mut out = String::new();
+        let mut rest = arg;
+        while let Some(start) = rest.find("${") {
+            out.push_str(&rest[..start]);
+            let Some(len) = rest[start..].find('}') else {
+                out.push_str(&rest[start..]);
+                return out;
+            };
+            let name = &rest[start + 2..start + len];
+            match self
+                .defines
+                .get(name)
+                .cloned()
+                .or_else(|| std::env::var(name).ok())
+            {
+                Some(value) => out.push_str(&value),
+                None => {
+                    warn!("Variable ${{{name}}} is not defined");
+                    out.push_str(&rest[start..start + len + 1]);
+                }
+            }
+            rest = &rest[start + len + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Loads a main server config (usually `httpd.conf`) the way httpd does:
+/// `Include` and `IncludeOptional` are replaced by the directives of the
+/// files they match, in order, and relative paths are resolved against
+/// `ServerRoot`. Conditionals and variables are evaluated along the way.
+pub struct IncludeResolver {
+    server_root: PathBuf,
+    context: ApacheContext,
+    stack: Vec<PathBuf>,
+    /// How deep inside sections `resolve` is, includes don't count.
+    depth: usize,
+    /// The file of every top-level `<VirtualHost>`, in order.
+    sources: Vec<PathBuf>,
+}
+
+impl IncludeResolver {
+    /// `server_root` defaults to the directory holding the main config and
+    /// is updated whenever a `ServerRoot` directive is seen.
+    pub fn new(server_root: PathBuf, context: ApacheContext) -> Self {
+        Self {
+            server_root,
+            context,
+            stack: vec![],
+            depth: 0,
+            sources: vec![],
+        }
+    }
+
+    /// The file each `<VirtualHost>` returned by `load` was read from.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// The ServerRoot, as last set by a `ServerRoot` directive.
+    pub fn server_root(&self) -> &Path {
+        &self.server_root
+    }
+
+    pub fn load(&mut self, file_path: &Path) -> ParserResult<Vec<Directive<Apache>>> {
+        if self.stack.iter().any(|p| p == file_path) {
+            return Err(ParserError::Include(format!(
+                "{} includes itself",
+                file_path.display()
+            )));
+        }
+        if self.stack.len() >= MAX_INCLUDE_DEPTH {
+            return Err(ParserError::Include(format!(
+                "{} is nested more than {MAX_INCLUDE_DEPTH} includes deep",
+                file_path.display()
+            )));
+        }
+        debug!("Loading file: {:?}", file_path);
+        let data = std::fs::read(file_path)?;
+        let directives = Directive::<Apache>::parse(&data).map_err(|err| {
+            ParserError::Include(format!("unable to parse {}: {err}", file_path.display()))
+        })?;
+
+        self.stack.push(file_path.to_path_buf());
+        let resolved = self.resolve(directives);
+        self.stack.pop();
+        resolved
+    }
+
+    fn resolve(
+        &mut self,
+        directives: Vec<Directive<Apache>>,
+    ) -> ParserResult<Vec<Directive<Apache>>> {
+        let mut out = vec![];
+        for directive in directives {
+            let mut directive = match self.context.preprocess(directive) {
+                Preprocessed::Keep(directive) => directive,
+                Preprocessed::Splice(children) => {
+                    out.extend(self.resolve(children)?);
+                    continue;
+                }
+                Preprocessed::Drop => continue,
+            };
+            let name = directive.name.to_lowercase();
+            match name.as_str() {
+                "serverroot" => {
+                    // a relative root is taken from the current one
+                    if let Some(root) = directive.args.first() {
+                        self.server_root = self.server_root.join(root);
+                    }
+                    out.push(directive);
+                }
+                "include" | "includeoptional" => {
+                    let optional = name == "includeoptional";
+                    let Some(pattern) = directive.args.first() else {
+                        continue;
+                    };
+                    for path in self.expand(pattern, optional)? {
+                        out.extend(self.load(&path)?);
+                    }
+                }
+                _ => {
+                    if name == "virtualhost" && self.depth == 0 {
+                        self.sources.extend(self.stack.last().cloned());
+                    }
+                    if let Some(children) = directive.children.take() {
+                        self.depth += 1;
+                        let resolved = self.resolve(children);
+                        self.depth -= 1;
+                        directive.children = Some(resolved?);
+                    }
+                    out.push(directive);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Expands an include argument into the files it refers to, sorted the
+    /// way httpd reads them.
+    fn expand(&self, pattern: &str, optional: bool) -> ParserResult<Vec<PathBuf>> {
+        let path = Path::new(pattern);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.server_root.join(path)
+        };
+
+        let mut files = vec![];
+        if pattern.contains(['*', '?', '[']) {
+            let matches = glob::glob(&path.to_string_lossy())
+                .map_err(|err| ParserError::Include(format!("{pattern}: {err}")))?;
+            for entry in matches.flatten() {
+                files.extend(files_in(&entry));
+            }
+            if files.is_empty() && !optional {
+                warn!("Include {pattern} did not match any file");
+            }
+        } else if path.exists() {
+            files.extend(files_in(&path));
+        } else if !optional {
+            return Err(ParserError::Include(format!(
+                "{} does not exist",
+                path.display()
+            )));
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// A file is returned as is, a directory is expanded into every file below
+/// it.
+fn files_in(path: &Path) -> Vec<PathBuf> {
+    if path.is_dir() {
+        walkdir::WalkDir::new(path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Writes `files` (path and content) below a new temporary directory.
+    fn tree(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn vhost(name: &str) -> String {
+        format!("<VirtualHost *:80>\n    ServerName {name}\n</VirtualHost>\n")
+    }
+
+    fn load(dir: &TempDir, main: &str) -> (IncludeResolver, ParserResult<Vec<Directive<Apache>>>) {
+        let mut resolver = IncludeResolver::new(dir.path().to_path_buf(), ApacheContext::default());
+        let directives = resolver.load(&dir.path().join(main));
+        (resolver, directives)
+    }
+
+    fn server_names(directives: &[Directive<Apache>]) -> Vec<String> {
+        directives
+            .iter()
+            .filter(|directive| directive.name == "VirtualHost")
+            .flat_map(|directive| directive.children.iter().flatten())
+            .filter(|child| child.name == "ServerName")
+            .map(|child| child.args[0].clone())
+            .collect()
+    }
+
+    /// The names of the directives left once `config` is evaluated, with
+    /// their arguments.
+    fn evaluate(context: &mut ApacheContext, config: &str) -> Vec<String> {
+        let directives = Directive::<Apache>::parse(config.as_bytes()).unwrap();
+        context
+            .evaluate(directives)
+            .iter()
+            .filter(|directive| directive.name != "LoadModule")
+            .map(|directive| format!("{} {}", directive.name, directive.args.join(" ")))
+            .collect()
+    }
+
+    #[test]
+    fn if_module() {
+        let cases = [
+            (&[][..], "<IfModule mod_ssl.c>\nKept on\n</IfModule>", false),
+            (&[][..], "<IfModule !mod_ssl.c>\nKept on\n</IfModule>", true),
+            (&["mod_ssl.c"][..], "<IfModule ssl_module>\nKept on\n</IfModule>", true),
+            (&["ssl_module"][..], "<IfModule mod_ssl.c>\nKept on\n</IfModule>", true),
+            (&["ssl_module"][..], "<IfModule !ssl_module>\nKept on\n</IfModule>", false),
+            (&[][..], "<IfModule mod_so.c>\nKept on\n</IfModule>", true),
+            (
+                &[][..],
+                "LoadModule rewrite_module modules/mod_rewrite.so\n<IfModule mod_rewrite.c>\nKept on\n</IfModule>",
+                true,
+            ),
+        ];
+        for (modules, config, kept) in cases {
+            let modules = modules.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+            let mut context = ApacheContext::new(&modules, &[]);
+            let expected = if kept { vec!["Kept on"] } else { vec![] };
+            assert_eq!(
+                evaluate(&mut context, config),
+                expected,
+                "{modules:?} {config}"
+            );
+        }
+    }
+
+    #[test]
+    fn common_modules_are_loaded_for_directories() {
+        let config = "<IfModule mod_ssl.c>\nSSLEngine on\n</IfModule>";
+        assert!(evaluate(&mut ApacheContext::default(), config).is_empty());
+        assert_eq!(
+            evaluate(&mut ApacheContext::default().with_common_modules(), config),
+            vec!["SSLEngine on"]
+        );
+    }
+
+    #[test]
+    fn defines_apply_in_order() {
+        let cases = [
+            ("<IfDefine PROD>\nKept on\n</IfDefine>", vec![]),
+            (
+                "Define PROD\n<IfDefine PROD>\nKept on\n</IfDefine>",
+                vec!["Kept on"],
+            ),
+            ("<IfDefine PROD>\nKept on\n</IfDefine>\nDefine PROD", vec![]),
+            (
+                "Define PROD\nUndefine PROD\n<IfDefine !PROD>\nKept on\n</IfDefine>",
+                vec!["Kept on"],
+            ),
+            (
+                "Define PORT 8443\nListen ${PORT}\nDefine PORT 9443\nListen ${PORT}",
+                vec!["Listen 8443", "Listen 9443"],
+            ),
+        ];
+        for (config, expected) in cases {
+            let mut context = ApacheContext::default();
+            assert_eq!(evaluate(&mut context, config), expected, "{config}");
+        }
+    }
+
+    #[test]
+    fn variables() {
+        std::env::set_var("PROXYPARSER_TEST_ROOT", "/srv/www");
+        let cases = [
+            (
+                "DocumentRoot ${PROXYPARSER_TEST_ROOT}/site",
+                "DocumentRoot /srv/www/site",
+            ),
+            // defines win over the environment
+            (
+                "Define PROXYPARSER_TEST_ROOT /var/www\nDocumentRoot ${PROXYPARSER_TEST_ROOT}",
+                "DocumentRoot /var/www",
+            ),
+            (
+                "DocumentRoot ${PROXYPARSER_UNDEFINED}/site",
+                "DocumentRoot ${PROXYPARSER_UNDEFINED}/site",
+            ),
+            ("DocumentRoot ${unterminated", "DocumentRoot ${unterminated"),
+        ];
+        for (config, expected) in cases {
+            let mut context = ApacheContext::new(&[], &["HOST=example.com".to_string()]);
+            assert_eq!(evaluate(&mut context, config), vec![expected], "{config}");
+        }
+        let mut context = ApacheContext::new(&[], &["HOST=example.com".to_string()]);
+        assert_eq!(
+            evaluate(&mut context, "ServerName www.${HOST}"),
+            vec!["ServerName www.example.com"]
+        );
+    }
+
+    #[test]
+    fn globs_and_directories_are_read_in_order() {
+        let dir = tree(&[
+            ("httpd.conf", "Include conf.d/*.conf\nInclude sites\n"),
+            ("conf.d/b.conf", &vhost("b.example.com")),
+            ("conf.d/a.conf", &vhost("a.example.com")),
+            ("conf.d/skipped.txt", &vhost("txt.example.com")),
+            ("sites/z/last.conf", &vhost("z.example.com")),
+            ("sites/m.conf", &vhost("m.example.com")),
+        ]);
+        let (resolver, directives) = load(&dir, "httpd.conf");
+        assert_eq!(
+            server_names(&directives.unwrap()),
+            vec![
+                "a.example.com",
+                "b.example.com",
+                "m.example.com",
+                "z.example.com"
+            ]
+        );
+        let sources = resolver
+            .sources()
+            .iter()
+            .map(|source| source.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                "conf.d/a.conf",
+                "conf.d/b.conf",
+                "sites/m.conf",
+                "sites/z/last.conf"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn sources_follow_conditionals_but_not_nested_sections() {
+        let dir = tree(&[
+            (
+                "httpd.conf",
+                &format!(
+                    "{}<IfModule mod_ssl.c>\n{}</IfModule>\nInclude extra.conf\n",
+                    vhost("main.example.com"),
+                    vhost("skipped.example.com")
+                ),
+            ),
+            ("extra.conf", &vhost("extra.example.com")),
+        ]);
+        let (resolver, directives) = load(&dir, "httpd.conf");
+        assert_eq!(
+            server_names(&directives.unwrap()),
+            vec!["main.example.com", "extra.example.com"]
+        );
+        assert_eq!(
+            resolver.sources(),
+            [dir.path().join("httpd.conf"), dir.path().join("extra.conf")]
+        );
+    }
+
+    #[test]
+    fn relative_server_roots_build_on_the_current_one() {
+        let dir = tree(&[
+            ("httpd.conf", "ServerRoot conf\nInclude sites/*.conf\n"),
+            ("conf/sites/a.conf", &vhost("a.example.com")),
+        ]);
+        let (resolver, directives) = load(&dir, "httpd.conf");
+        assert_eq!(server_names(&directives.unwrap()), vec!["a.example.com"]);
+        assert_eq!(resolver.server_root(), dir.path().join("conf"));
+    }
+
+    #[test]
+    fn missing_includes() {
+        let cases = [
+            ("IncludeOptional missing.conf\n", true),
+            ("IncludeOptional missing/*.conf\n", true),
+            ("Include missing/*.conf\n", true),
+            ("Include missing.conf\n", false),
+        ];
+        for (config, loads) in cases {
+            let dir = tree(&[("httpd.conf", config)]);
+            let (_, directives) = load(&dir, "httpd.conf");
+            assert_eq!(directives.is_ok(), loads, "{config}");
+        }
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        let dir = tree(&[
+            ("httpd.conf", "Include a.conf\n"),
+            ("a.conf", "Include b.conf\n"),
+            ("b.conf", "Include a.conf\n"),
+        ]);
+        let (_, directives) = load(&dir, "httpd.conf");
+        let error = directives.unwrap_err().to_string();
+        assert!(error.contains("a.conf includes itself"), "{error}");
+    }
+
+    #[test]
+    fn deep_includes_are_refused() {
+        let files = (0..=MAX_INCLUDE_DEPTH)
+            .map(|i| (format!("{i}.conf"), format!("Include {}.conf\n", i + 1)))
+            .chain([(format!("{}.conf", MAX_INCLUDE_DEPTH + 1), String::new())])
+            .collect::<Vec<_>>();
+        let files = files
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_str()))
+            .collect::<Vec<_>>();
+        let dir = tree(&files);
+        let (_, directives) = load(&dir, "0.conf");
+        let error = directives.unwrap_err().to_string();
+        assert!(
+            error.contains(&format!(
+                "nested more than {MAX_INCLUDE_DEPTH} includes deep"
+            )),
+            "{error}"
+        );
+    }
+}
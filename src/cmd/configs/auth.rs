@@ -0,0 +1,448 @@
+//! Authentication: mod_auth_basic's `AuthType Basic` with mod_authn_file's
+//! `AuthUserFile`, and nginx's `auth_basic`.
+//!
+//! The htpasswd files are read once the configs are loaded, so that
+//! traefik's `basicAuth` gets the users inline. Hashes traefik can't check
+//! are reported and their users left out.
+//!
+//! Logins traefik can't check itself, such as LDAP (mod_authnz_ldap),
+//! OpenID Connect (mod_auth_openidc) or Shibboleth (mod_shib), are handed
+//! to a `forwardAuth` service set in the profile.
+
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    access::Login,
+    profile::Settings,
+    traefik::{BasicAuth, ForwardAuth, Middleware},
+};
+use crate::error::ParserResult;
+
+/// What a redacted htpasswd entry carries instead of the hash.
+const REDACTED: &str = "<redacted>";
+
+/// The prefixes of the hashes traefik checks: `htpasswd -m` (apr1), the
+/// crypt variant of MD5, `htpasswd -s` and `htpasswd -B`.
+const SUPPORTED_HASHES: [&str; 7] = ["$apr1$", "$1$", "{SHA}", "$2y$", "$2a$", "$2b$", "$2x$"];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AuthConfig {
+    /// `Basic`, `Digest`, `form`, or a module's own such as
+    /// `openid-connect`.
+    pub(super) auth_type: Option<String>,
+    /// `AuthName`, the realm.
+    pub(super) name: Option<String>,
+    pub(super) user_file: Option<String>,
+    pub(super) group_file: Option<String>,
+    /// `AuthBasicProvider`, `file` when not given.
+    pub(super) basic_provider: Option<Vec<String>>,
+    /// `AuthLDAPURL`
+    pub(super) ldap_url: Option<String>,
+    /// `user_file` resolved against the ServerRoot.
+    #[serde(skip)]
+    pub(super) user_file_path: Option<PathBuf>,
+    /// The `user:hash` entries of `user_file` traefik can check, once read.
+    #[serde(skip)]
+    pub(super) users: Option<Vec<String>>,
+}
+
+impl AuthConfig {
+    /// Records an `Auth*` directive, returning false for any other.
+    pub(super) fn set(&mut self, name: &str, args: &[String]) -> bool {
+        let Some(arg) = args.first() else {
+            return false;
+        };
+        match name {
+            "AuthType" => self.auth_type = Some(arg.to_string()),
+            "AuthName" => self.name = Some(arg.to_string()),
+            "AuthUserFile" => self.user_file = Some(arg.to_string()),
+            "AuthGroupFile" => self.group_file = Some(arg.to_string()),
+            "AuthBasicProvider" => self.basic_provider = Some(args.to_vec()),
+            "AuthLDAPURL" => self.ldap_url = Some(arg.to_string()),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Takes what isn't set from an enclosing section, as httpd merges
+    /// `Auth*` directives.
+    pub(super) fn inherit(&mut self, parent: &AuthConfig) {
+        let parent = parent.clone();
+        self.auth_type = self.auth_type.take().or(parent.auth_type);
+        self.name = self.name.take().or(parent.name);
+        self.group_file = self.group_file.take().or(parent.group_file);
+        self.basic_provider = self.basic_provider.take().or(parent.basic_provider);
+        self.ldap_url = self.ldap_url.take().or(parent.ldap_url);
+        if self.user_file.is_none() {
+            self.user_file = parent.user_file;
+            self.user_file_path = parent.user_file_path;
+            self.users = parent.users;
+        }
+    }
+
+    /// The provider checking the login when traefik can't, or none for
+    /// `AuthType Basic` against a file.
+    pub(super) fn provider(&self) -> Option<AuthProvider> {
+        let auth_type = self.auth_type.as_deref()?.to_lowercase();
+        let providers = self.basic_provider.iter().flatten();
+        Some(match auth_type.as_str() {
+            "openid-connect" | "auth-openidc" | "oauth20" => AuthProvider::Oidc,
+            "shibboleth" => AuthProvider::Shibboleth,
+            "basic" => {
+                let mut providers = providers.map(|provider| provider.to_lowercase());
+                match providers.find(|provider| provider != "file") {
+                    Some(provider) if provider == "ldap" => AuthProvider::Ldap,
+                    Some(provider) => AuthProvider::Other(provider),
+                    None if self.ldap_url.is_some() => AuthProvider::Ldap,
+                    None => return None,
+                }
+            }
+            _ => AuthProvider::Other(auth_type),
+        })
+    }
+
+    /// Where the provider is reached in httpd, such as the LDAP URL.
+    pub(super) fn provider_detail(&self, oidc: &OidcConfig) -> Option<String> {
+        match self.provider()? {
+            AuthProvider::Ldap => self.ldap_url.clone(),
+            AuthProvider::Oidc => oidc.provider_metadata_url.clone(),
+            _ => None,
+        }
+    }
+
+    /// Like httpd, relative paths start at the ServerRoot, or at the file
+    /// the host was read from when there is none.
+    pub(super) fn resolve_user_file(
+        &mut self,
+        server_root: Option<&Path>,
+        source: Option<&Path>,
+    ) -> Option<&Path> {
+        let file = Path::new(self.user_file.as_ref()?);
+        let path = if file.is_absolute() {
+            file.to_path_buf()
+        } else if let Some(server_root) = server_root {
+            server_root.join(file)
+        } else {
+            source
+                .and_then(Path::parent)
+                .map(|dir| dir.join(file))
+                .unwrap_or_else(|| file.to_path_buf())
+        };
+        self.user_file_path = Some(path);
+        self.user_file_path.as_deref()
+    }
+
+    /// Replaces the hashes of the users read with a placeholder.
+    pub(super) fn redact(&mut self) {
+        for user in self.users.iter_mut().flatten() {
+            if let Some((name, _)) = user.split_once(':') {
+                *user = format!("{name}:{REDACTED}");
+            }
+        }
+    }
+
+    /// The `basicAuth` middleware asking for the login, or why traefik
+    /// can't. Requirements on groups are reported and any user of the file
+    /// is let in instead.
+    pub(super) fn to_middleware(
+        &self,
+        host: &str,
+        section: &str,
+        login: &Login,
+    ) -> Result<Middleware, String> {
+        if self.auth_type.is_none() {
+            return Err("no AuthType is set".to_string());
+        }
+        let Some(user_file) = &self.user_file else {
+            return Err("no AuthUserFile is set".to_string());
+        };
+        let mut basic_auth = BasicAuth {
+            realm: self.name.clone(),
+            ..Default::default()
+        };
+        match &self.users {
+            Some(users) => {
+                basic_auth.users = match login {
+                    Login::Users(names) => {
+                        for name in names {
+                            if !users.iter().any(|user| user_name(user) == name) {
+                                warn!("{host}: user {name} of {section} isn't in {user_file}");
+                            }
+                        }
+                        users
+                            .iter()
+                            .filter(|user| names.iter().any(|name| name == user_name(user)))
+                            .cloned()
+                            .collect()
+                    }
+                    Login::AnyUser => users.clone(),
+                    Login::Other(requirement) => {
+                        warn!(
+                            "{host}: {requirement} in {section} isn't translated, any user of {user_file} is let in"
+                        );
+                        users.clone()
+                    }
+                };
+                if basic_auth.users.is_empty() {
+                    return Err(format!("no user of {user_file} can log in"));
+                }
+            }
+            // traefik reads the file itself, as it is
+            None => {
+                if *login != Login::AnyUser {
+                    warn!(
+                        "{host}: {login} in {section} isn't translated, any user of {user_file} is let in"
+                    );
+                }
+                let path = self
+                    .user_file_path
+                    .as_deref()
+                    .unwrap_or(Path::new(user_file));
+                basic_auth.users_file = Some(path.display().to_string());
+            }
+        }
+        Ok(Middleware {
+            basic_auth: Some(basic_auth),
+            ..Default::default()
+        })
+    }
+}
+
+/// A login provider traefik has no middleware for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthProvider {
+    Ldap,
+    Oidc,
+    Shibboleth,
+    /// Another `AuthType` or `AuthBasicProvider`, e.g. `Digest` or `dbd`.
+    Other(String),
+}
+
+impl std::fmt::Display for AuthProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthProvider::Ldap => write!(f, "ldap"),
+            AuthProvider::Oidc => write!(f, "oidc"),
+            AuthProvider::Shibboleth => write!(f, "shibboleth"),
+            AuthProvider::Other(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A path whose login is left to an external provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtectedPath {
+    pub path: String,
+    pub provider: AuthProvider,
+    /// Where httpd reaches the provider, e.g. the LDAP URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// mod_auth_openidc's provider settings, given per host or for the server.
+/// The client secret isn't kept.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OidcConfig {
+    pub(super) provider_metadata_url: Option<String>,
+    pub(super) redirect_uri: Option<String>,
+    pub(super) client_id: Option<String>,
+}
+
+impl OidcConfig {
+    /// Records an `OIDC*` directive, returning false for any other.
+    pub(super) fn set(&mut self, name: &str, args: &[String]) -> bool {
+        let Some(arg) = args.first() else {
+            return false;
+        };
+        match name {
+            "OIDCProviderMetadataURL" => self.provider_metadata_url = Some(arg.to_string()),
+            "OIDCRedirectURI" => self.redirect_uri = Some(arg.to_string()),
+            "OIDCClientID" => self.client_id = Some(arg.to_string()),
+            _ => return false,
+        }
+        true
+    }
+
+    pub(super) fn inherit(&mut self, server: &OidcConfig) {
+        let server = server.clone();
+        self.provider_metadata_url = self
+            .provider_metadata_url
+            .take()
+            .or(server.provider_metadata_url);
+        self.redirect_uri = self.redirect_uri.take().or(server.redirect_uri);
+        self.client_id = self.client_id.take().or(server.client_id);
+    }
+}
+
+/// The `forwardAuth` middleware handing the login to the provider's
+/// service from the profile.
+pub(super) fn to_forward_auth(
+    provider: &AuthProvider,
+    host: &str,
+    settings: &Settings,
+) -> Middleware {
+    let address = settings
+        .forward_auth_address
+        .replace("{provider}", &provider.to_string())
+        .replace("{host}", host);
+    Middleware {
+        forward_auth: Some(ForwardAuth {
+            address,
+            trust_forward_header: settings.forward_auth_trust_forward_header,
+            auth_response_headers: settings.forward_auth_response_headers.clone(),
+        }),
+        ..Default::default()
+    }
+}
+
+fn user_name(entry: &str) -> &str {
+    entry.split_once(':').map_or(entry, |(name, _)| name)
+}
+
+/// Reads the `user:hash` entries of an htpasswd file, leaving out the ones
+/// traefik can't check.
+pub(super) fn read_htpasswd(path: &Path) -> ParserResult<Vec<String>> {
+    let data = std::fs::read_to_string(path)?;
+    let mut users = vec![];
+    for line in data.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, hash)) = line.split_once(':') else {
+            warn!("{}: skipping a line without a user name", path.display());
+            continue;
+        };
+        if !SUPPORTED_HASHES
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            warn!(
+                "{}: skipping {name}, traefik only checks MD5 (apr1), SHA1 and bcrypt hashes ({})",
+                path.display(),
+                hash_format(hash)
+            );
+            continue;
+        }
+        users.push(format!("{name}:{hash}"));
+    }
+    Ok(users)
+}
+
+/// Names the format of a hash traefik doesn't check, without showing it.
+fn hash_format(hash: &str) -> &'static str {
+    if hash.starts_with("$5$") {
+        "SHA-256 crypt"
+    } else if hash.starts_with("$6$") {
+        "SHA-512 crypt"
+    } else if hash.len() == 13 && !hash.starts_with('$') {
+        "DES crypt"
+    } else {
+        "plain text or unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const HTPASSWD: &str = "\
+# managed by hand
+alice:$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/
+bob:$2b$05$tlHGcfIcN1uQWvMYI6s3wOl5m.RqeNbC3D0E4MI6jX5kmiLQv5zbu
+carol:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=
+
+dave:$6$salt$IxDD3jeSOb5eB1CX5LBsqZFVkJdido3OUILO5Ifz5iwMuTS4XMS130MTSuDDl3aCI6WouIL9AjRbLCelDCy.g.
+erin:rl4Cm6jNPOWWg
+frank:plain
+no user name
+";
+
+    fn users_file() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("htpasswd");
+        fs::write(&path, HTPASSWD).unwrap();
+        (dir, path)
+    }
+
+    fn auth_config() -> (tempfile::TempDir, AuthConfig) {
+        let (dir, path) = users_file();
+        let auth = AuthConfig {
+            auth_type: Some("Basic".to_string()),
+            name: Some("Staff".to_string()),
+            user_file: Some(path.display().to_string()),
+            users: Some(read_htpasswd(&path).unwrap()),
+            user_file_path: Some(path),
+            ..Default::default()
+        };
+        (dir, auth)
+    }
+
+    fn users(middleware: Middleware) -> Vec<String> {
+        middleware.basic_auth.unwrap().users
+    }
+
+    #[test]
+    fn only_hashes_traefik_checks_are_read() {
+        let (_dir, path) = users_file();
+        let names: Vec<_> = read_htpasswd(&path)
+            .unwrap()
+            .iter()
+            .map(|user| user_name(user).to_string())
+            .collect();
+        assert_eq!(names, vec!["alice", "bob", "carol"]);
+        assert!(read_htpasswd(&path.with_file_name("missing")).is_err());
+    }
+
+    #[test]
+    fn hash_formats() {
+        let cases = [
+            ("$5$salt$hash", "SHA-256 crypt"),
+            ("$6$salt$hash", "SHA-512 crypt"),
+            ("rl4Cm6jNPOWWg", "DES crypt"),
+            ("secret", "plain text or unknown"),
+        ];
+        for (hash, expected) in cases {
+            assert_eq!(hash_format(hash), expected, "{hash}");
+        }
+    }
+
+    #[test]
+    fn redaction_keeps_the_user_names() {
+        let (_dir, mut auth) = auth_config();
+        auth.redact();
+        assert_eq!(
+            auth.users.unwrap(),
+            vec!["alice:<redacted>", "bob:<redacted>", "carol:<redacted>"]
+        );
+    }
+
+    #[test]
+    fn logins() {
+        let (_dir, auth) = auth_config();
+        let all = users(auth.to_middleware("host", "/", &Login::AnyUser).unwrap());
+        assert_eq!(all.len(), 3);
+        let login = Login::Users(vec!["bob".to_string(), "zoe".to_string()]);
+        let bob = users(auth.to_middleware("host", "/", &login).unwrap());
+        assert_eq!(bob, vec![HTPASSWD.lines().nth(2).unwrap()]);
+        // nobody left to log in, the caller refuses every client
+        let login = Login::Users(vec!["erin".to_string()]);
+        assert!(auth.to_middleware("host", "/", &login).is_err());
+    }
+
+    #[test]
+    fn unread_files_are_left_to_traefik() {
+        let (_dir, mut auth) = auth_config();
+        auth.users = None;
+        let middleware = auth.to_middleware("host", "/", &Login::AnyUser).unwrap();
+        let basic_auth = middleware.basic_auth.unwrap();
+        assert_eq!(basic_auth.users_file, auth.user_file);
+        assert_eq!(basic_auth.realm.as_deref(), Some("Staff"));
+        assert!(basic_auth.users.is_empty());
+    }
+}
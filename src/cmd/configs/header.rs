@@ -0,0 +1,425 @@
+//! mod_headers: `Header` and `RequestHeader`, and nginx's `add_header` and
+//! `proxy_set_header`.
+
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::traefik::{Headers, Middleware};
+
+/// Which side of the exchange a header directive changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderKind {
+    Request,
+    #[default]
+    Response,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderAction {
+    #[default]
+    Set,
+    SetIfEmpty,
+    Append,
+    Add,
+    Merge,
+    Unset,
+    /// `edit` and `edit*`, the latter replacing every match.
+    Edit {
+        all: bool,
+    },
+    Echo,
+    Note,
+}
+
+impl HeaderAction {
+    fn parse(action: &str) -> Option<Self> {
+        Some(match action.to_lowercase().as_str() {
+            "set" => HeaderAction::Set,
+            "setifempty" => HeaderAction::SetIfEmpty,
+            "append" => HeaderAction::Append,
+            "add" => HeaderAction::Add,
+            "merge" => HeaderAction::Merge,
+            "unset" => HeaderAction::Unset,
+            "edit" => HeaderAction::Edit { all: false },
+            "edit*" => HeaderAction::Edit { all: true },
+            "echo" => HeaderAction::Echo,
+            "note" => HeaderAction::Note,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Header {
+    pub(super) kind: HeaderKind,
+    pub(super) action: HeaderAction,
+    /// The header name, or the regex for `echo`.
+    pub(super) name: String,
+    /// The value, or the regex for `edit`.
+    pub(super) value: Option<String>,
+    /// The replacement of `edit`.
+    pub(super) replacement: Option<String>,
+    /// `Header always`, which also applies to error responses.
+    pub(super) always: bool,
+    /// `early`, applied before the request is mapped to a handler.
+    pub(super) early: bool,
+    /// `env=[!]var` or `expr=...` limiting when the header is changed.
+    pub(super) condition: Option<String>,
+}
+
+impl Header {
+    /// Parses `Header [always|onsuccess] action header [[expr=]value
+    /// [replacement] [early|env=[!]var|expr=expression]]`, or the same
+    /// without the first argument for `RequestHeader`.
+    pub(super) fn from_args(name: &str, args: &[String]) -> Option<Self> {
+        let kind = match name.to_lowercase().as_str() {
+            "header" => HeaderKind::Response,
+            "requestheader" => HeaderKind::Request,
+            _ => return None,
+        };
+        let mut args = args.iter().peekable();
+        let mut always = false;
+        if kind == HeaderKind::Response {
+            match args.peek().map(|arg| arg.to_lowercase()).as_deref() {
+                Some("always") => {
+                    always = true;
+                    args.next();
+                }
+                Some("onsuccess") => {
+                    args.next();
+                }
+                _ => {}
+            }
+        }
+        let action = HeaderAction::parse(args.next()?)?;
+        let mut header = Header {
+            kind,
+            name: args.next()?.to_string(),
+            always,
+            ..Default::default()
+        };
+        // `echo` only takes the regex of the headers to copy
+        if !matches!(action, HeaderAction::Unset | HeaderAction::Echo) {
+            header.value = Some(args.next()?.to_string());
+        }
+        if let HeaderAction::Edit { .. } = action {
+            header.replacement = Some(args.next()?.to_string());
+        }
+        header.action = action;
+        for arg in args {
+            if arg.eq_ignore_ascii_case("early") {
+                header.early = true;
+            } else {
+                header.condition = Some(arg.to_string());
+            }
+        }
+        Some(header)
+    }
+
+    /// A header set as is, e.g. by nginx's `add_header` and
+    /// `proxy_set_header`.
+    pub(super) fn set(kind: HeaderKind, name: &str, value: &str) -> Self {
+        Header {
+            kind,
+            name: name.to_string(),
+            value: Some(value.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// The value traefik should send, where an empty value removes the
+    /// header, or why the directive can't be expressed.
+    fn traefik_value(&self) -> Result<String, String> {
+        if let Some(condition) = &self.condition {
+            return Err(format!(
+                "traefik can't apply headers conditionally ({condition})"
+            ));
+        }
+        let value = self.value.clone().unwrap_or_default();
+        if value.starts_with("expr=") || value.contains("%{") {
+            return Err("traefik can't compute header values".to_string());
+        }
+        match self.action {
+            HeaderAction::Set | HeaderAction::SetIfEmpty => Ok(value),
+            // traefik replaces the header instead of adding to it
+            HeaderAction::Append | HeaderAction::Add | HeaderAction::Merge => Ok(value),
+            HeaderAction::Unset => Ok(String::new()),
+            HeaderAction::Edit { .. } => Err("traefik can't edit header values".to_string()),
+            HeaderAction::Echo => Err("traefik can't echo request headers".to_string()),
+            HeaderAction::Note => Err("notes only exist inside httpd".to_string()),
+        }
+    }
+
+    /// Whether traefik's value differs from Apache's for some requests.
+    fn is_approximate(&self) -> bool {
+        matches!(
+            self.action,
+            HeaderAction::SetIfEmpty
+                | HeaderAction::Append
+                | HeaderAction::Add
+                | HeaderAction::Merge
+        )
+    }
+}
+
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let directive = match self.kind {
+            HeaderKind::Request => "RequestHeader",
+            HeaderKind::Response if self.always => "Header always",
+            HeaderKind::Response => "Header",
+        };
+        let action = match &self.action {
+            HeaderAction::Set => "set",
+            HeaderAction::SetIfEmpty => "setifempty",
+            HeaderAction::Append => "append",
+            HeaderAction::Add => "add",
+            HeaderAction::Merge => "merge",
+            HeaderAction::Unset => "unset",
+            HeaderAction::Edit { all: false } => "edit",
+            HeaderAction::Edit { all: true } => "edit*",
+            HeaderAction::Echo => "echo",
+            HeaderAction::Note => "note",
+        };
+        write!(f, "{directive} {action} {}", self.name)
+    }
+}
+
+/// The `headers` middleware of the directives, applied in order so a later
+/// `set` or `unset` wins. Well-known response headers use traefik's typed
+/// options, the rest become custom headers. Directives traefik can't
+/// express are reported and skipped.
+pub(super) fn to_middleware(host: &str, headers: &[Header]) -> Option<Middleware> {
+    let mut request = BTreeMap::new();
+    let mut response = BTreeMap::new();
+    for header in headers {
+        let value = match header.traefik_value() {
+            Ok(value) => value,
+            Err(reason) => {
+                warn!("{host}: {header} can't be translated: {reason}");
+                continue;
+            }
+        };
+        if header.is_approximate() {
+            warn!("{host}: {header} is sent as a plain set");
+        }
+        let target = match header.kind {
+            HeaderKind::Request => &mut request,
+            HeaderKind::Response => &mut response,
+        };
+        target.insert(header.name.clone(), value);
+    }
+    if request.is_empty() && response.is_empty() {
+        return None;
+    }
+    let mut typed = Headers::default();
+    // removing a header stays a custom header with an empty value
+    response.retain(|name, value| value.is_empty() || !set_known(&mut typed, name, value));
+    Some(Middleware {
+        headers: Some(Headers {
+            custom_request_headers: request,
+            custom_response_headers: response,
+            ..typed
+        }),
+        ..Default::default()
+    })
+}
+
+/// Sets the typed option matching a well-known response header, returning
+/// false when there is none or the value doesn't fit it.
+fn set_known(headers: &mut Headers, name: &str, value: &str) -> bool {
+    let list = |value: &str| {
+        value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect::<Vec<_>>()
+    };
+    match name.to_lowercase().as_str() {
+        "strict-transport-security" => return set_sts(headers, value),
+        "x-frame-options" if value.eq_ignore_ascii_case("deny") => headers.frame_deny = Some(true),
+        "x-frame-options" => headers.custom_frame_options_value = Some(value.to_string()),
+        "x-content-type-options" if value.eq_ignore_ascii_case("nosniff") => {
+            headers.content_type_nosniff = Some(true)
+        }
+        "x-xss-protection" if value.replace(' ', "") == "1;mode=block" => {
+            headers.browser_xss_filter = Some(true)
+        }
+        "content-security-policy" => headers.content_security_policy = Some(value.to_string()),
+        "content-security-policy-report-only" => {
+            headers.content_security_policy_report_only = Some(value.to_string())
+        }
+        "referrer-policy" => headers.referrer_policy = Some(value.to_string()),
+        "permissions-policy" => headers.permissions_policy = Some(value.to_string()),
+        "access-control-allow-origin" => headers.access_control_allow_origin_list = list(value),
+        "access-control-allow-methods" => headers.access_control_allow_methods = list(value),
+        "access-control-allow-headers" => headers.access_control_allow_headers = list(value),
+        "access-control-expose-headers" => headers.access_control_expose_headers = list(value),
+        "access-control-allow-credentials" if value.eq_ignore_ascii_case("true") => {
+            headers.access_control_allow_credentials = Some(true)
+        }
+        "access-control-max-age" => match value.parse() {
+            Ok(seconds) => headers.access_control_max_age = Some(seconds),
+            Err(_) => return false,
+        },
+        _ => return false,
+    }
+    true
+}
+
+/// `max-age=31536000; includeSubDomains; preload`
+fn set_sts(headers: &mut Headers, value: &str) -> bool {
+    let mut seconds = None;
+    let mut include_subdomains = false;
+    let mut preload = false;
+    for directive in value.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.split_once('=') {
+            Some((key, max_age)) if key.trim().eq_ignore_ascii_case("max-age") => {
+                match max_age.trim().trim_matches('"').parse::<i64>() {
+                    Ok(max_age) => seconds = Some(max_age),
+                    Err(_) => return false,
+                }
+            }
+            _ if directive.eq_ignore_ascii_case("includesubdomains") => include_subdomains = true,
+            _ if directive.eq_ignore_ascii_case("preload") => preload = true,
+            _ => return false,
+        }
+    }
+    let Some(seconds) = seconds else {
+        return false;
+    };
+    headers.sts_seconds = Some(seconds);
+    headers.sts_include_subdomains = include_subdomains.then_some(true);
+    headers.sts_preload = preload.then_some(true);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use misc_conf::{apache::Apache, ast::*};
+
+    use super::*;
+
+    fn headers(apache: &str) -> Vec<Header> {
+        Directive::<Apache>::parse(apache.as_bytes())
+            .unwrap()
+            .iter()
+            .filter_map(|directive| Header::from_args(&directive.name, &directive.args))
+            .collect()
+    }
+
+    #[test]
+    fn directives() {
+        let cases = [
+            ("Header set X-A 1", "Header set X-A", Some("1"), None),
+            (
+                "Header always setifempty X-A 1 env=!internal",
+                "Header always setifempty X-A",
+                Some("1"),
+                Some("env=!internal"),
+            ),
+            ("Header onsuccess unset X-A", "Header unset X-A", None, None),
+            ("Header echo ^X-", "Header echo ^X-", None, None),
+            (
+                "Header edit* Set-Cookie ^(.*)$ $1;HttpOnly",
+                "Header edit* Set-Cookie",
+                Some("^(.*)$"),
+                None,
+            ),
+            (
+                "RequestHeader set X-Forwarded-Proto https early",
+                "RequestHeader set X-Forwarded-Proto",
+                Some("https"),
+                None,
+            ),
+        ];
+        for (config, display, value, condition) in cases {
+            let header = &headers(config)[0];
+            assert_eq!(header.to_string(), display, "{config}");
+            assert_eq!(header.value.as_deref(), value, "{config}");
+            assert_eq!(header.condition.as_deref(), condition, "{config}");
+        }
+        assert!(
+            headers("Header set X-A\nHeader frobnicate X-A 1\nRequestHeader always set X-A 1")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn middleware() {
+        let middleware = to_middleware(
+            "www.example.com",
+            &headers(
+                r#"
+Header always set Strict-Transport-Security "max-age=31536000; includeSubDomains; preload"
+Header set X-Frame-Options SAMEORIGIN
+Header set X-Content-Type-Options nosniff
+Header set Access-Control-Allow-Origin "https://a.example.com, https://b.example.com"
+Header set Access-Control-Allow-Methods "GET,POST"
+Header set Access-Control-Allow-Credentials true
+Header set Access-Control-Max-Age 600
+Header set X-Served-By proxy
+Header set X-Served-By traefik
+Header unset Server
+Header set X-Request-Id "%{UNIQUE_ID}e"
+Header set X-Internal yes env=internal
+RequestHeader set X-Forwarded-Proto https
+"#,
+            ),
+        )
+        .unwrap();
+        let headers = middleware.headers.unwrap();
+        assert_eq!(headers.sts_seconds, Some(31536000));
+        assert_eq!(headers.sts_include_subdomains, Some(true));
+        assert_eq!(headers.sts_preload, Some(true));
+        assert_eq!(headers.frame_deny, None);
+        assert_eq!(
+            headers.custom_frame_options_value.as_deref(),
+            Some("SAMEORIGIN")
+        );
+        assert_eq!(headers.content_type_nosniff, Some(true));
+        assert_eq!(
+            headers.access_control_allow_origin_list,
+            vec!["https://a.example.com", "https://b.example.com"]
+        );
+        assert_eq!(headers.access_control_allow_methods, vec!["GET", "POST"]);
+        assert_eq!(headers.access_control_allow_credentials, Some(true));
+        assert_eq!(headers.access_control_max_age, Some(600));
+        // a later set wins, unset is an empty value, the rest is skipped
+        assert_eq!(
+            headers.custom_response_headers,
+            BTreeMap::from([
+                ("Server".to_string(), String::new()),
+                ("X-Served-By".to_string(), "traefik".to_string()),
+            ])
+        );
+        assert_eq!(
+            headers.custom_request_headers,
+            BTreeMap::from([("X-Forwarded-Proto".to_string(), "https".to_string())])
+        );
+    }
+
+    #[test]
+    fn frame_deny_and_bad_sts() {
+        let headers = to_middleware(
+            "www.example.com",
+            &headers(
+                "Header set X-Frame-Options DENY\nHeader set Strict-Transport-Security max-age=soon",
+            ),
+        )
+        .unwrap()
+        .headers
+        .unwrap();
+        assert_eq!(headers.frame_deny, Some(true));
+        assert_eq!(headers.sts_seconds, None);
+        assert_eq!(
+            headers.custom_response_headers["Strict-Transport-Security"],
+            "max-age=soon"
+        );
+        assert!(to_middleware("www.example.com", &[]).is_none());
+    }
+}
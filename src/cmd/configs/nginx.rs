@@ -0,0 +1,1295 @@
+use std::collections::{BTreeMap, HashSet};
+
+use log::{debug, warn};
+use misc_conf::{ast::Directive, nginx::Nginx};
+
+use super::{
+    access::{self, Access, AccessRule, Login},
+    auth::AuthConfig,
+    go_duration,
+    header::{Header, HeaderAction, HeaderKind},
+    redirect::{Redirect, NGINX_VARIABLES},
+    regex_escape,
+    rewrite::RewriteFlags,
+    Balancer, BalancerMember, Location, LocationKind, ProxyConfig, ProxyPass, RewriteRule,
+    SslConfig, VirtualHost, EXACT_PRIORITY, PREFERRED_PRIORITY,
+};
+
+impl From<Directive<Nginx>> for ProxyConfig {
+    fn from(directive: Directive<Nginx>) -> Self {
+        let mut pc = ProxyConfig::default();
+        match directive.name.as_str() {
+            "server" if directive.children.is_some() => {
+                let virtual_host = VirtualHost::from(directive);
+                pc.virtual_hosts.push(virtual_host);
+            }
+            "http" => {
+                for child in directive.children.unwrap_or_default() {
+                    pc.merge(ProxyConfig::from(child));
+                }
+            }
+            "upstream" => {
+                pc.balancers.extend(nginx_upstream(&directive));
+            }
+            other => {
+                pc.meta.insert(
+                    other.to_string(),
+                    serde_json::to_string(&directive.args).unwrap(),
+                );
+            }
+        };
+        pc
+    }
+}
+
+impl From<Directive<Nginx>> for VirtualHost {
+    fn from(directive: Directive<Nginx>) -> Self {
+        let mut virtual_host = VirtualHost {
+            ssl_config: SslConfig::default(),
+            ..Default::default()
+        };
+        let mut connect_timeout = None;
+        for child in directive.children.iter().flatten() {
+            match child.name.as_str() {
+                "listen" => {
+                    if let Some(address) = child.args.first() {
+                        virtual_host.listen.push(normalize_listen(address));
+                    }
+                    if child.args.iter().any(|arg| arg == "ssl") {
+                        virtual_host.ssl_config.enabled = true;
+                    }
+                }
+                "server_name" => {
+                    let mut names = child.args.iter();
+                    if let Some(name) = names.next() {
+                        virtual_host.server_name = name.to_string();
+                    }
+                    virtual_host
+                        .server_aliases
+                        .extend(names.map(|name| name.to_string()));
+                }
+                "root" => {
+                    if let Some(root) = child.args.first() {
+                        virtual_host.document_root = root.to_string();
+                    }
+                }
+                "access_log" => {
+                    if let Some(log) = child.args.first() {
+                        virtual_host.custom_log = log.to_string();
+                    }
+                }
+                "error_log" => {
+                    if let Some(level) = child.args.get(1) {
+                        virtual_host.log_level = level.to_string();
+                    }
+                }
+                "add_header" | "proxy_set_header" => {
+                    virtual_host.headers.extend(nginx_header(child));
+                }
+                "rewrite" => {
+                    virtual_host.rewrite_engine = true;
+                    virtual_host.rewrite_rules.extend(nginx_rewrite(child));
+                }
+                "ssl" => {
+                    if let Some(on) = child.args.first() {
+                        virtual_host.ssl_config.enabled = on.eq_ignore_ascii_case("on");
+                    }
+                }
+                "ssl_certificate" => {
+                    if let Some(file) = child.args.first() {
+                        virtual_host.ssl_config.certificate_file = file.to_string();
+                    }
+                }
+                "ssl_certificate_key" => {
+                    if let Some(file) = child.args.first() {
+                        virtual_host.ssl_config.key_file = file.to_string();
+                    }
+                }
+                "ssl_prefer_server_ciphers" => {
+                    if let Some(on) = child.args.first() {
+                        virtual_host.ssl_config.honor_cipher_order =
+                            Some(on.eq_ignore_ascii_case("on"));
+                    }
+                }
+                "ssl_ciphers" => {
+                    if let Some(ciphers) = child.args.first() {
+                        virtual_host.ssl_config.ciphers = Some(ciphers.to_string());
+                    }
+                }
+                "ssl_protocols" => {
+                    virtual_host.ssl_config.protocols = Some(child.args.clone());
+                }
+                "proxy_ssl_verify" => {
+                    if let Some(on) = child.args.first() {
+                        let verify = if on == "on" { "require" } else { "none" };
+                        virtual_host.ssl_proxy.verify = Some(verify.to_string());
+                    }
+                }
+                "proxy_ssl_trusted_certificate" => {
+                    virtual_host.ssl_proxy.ca_certificate_file = child.args.first().cloned();
+                }
+                "proxy_read_timeout" | "keepalive_timeout" => {
+                    let name = if child.name == "keepalive_timeout" {
+                        "KeepAliveTimeout"
+                    } else {
+                        "ProxyTimeout"
+                    };
+                    virtual_host.timeouts.set(name, &child.args);
+                }
+                "proxy_connect_timeout" => {
+                    connect_timeout = child.args.first().cloned();
+                }
+                "proxy_ssl_name" => {
+                    virtual_host.ssl_proxy.server_name = child.args.first().cloned();
+                }
+                "return" => {
+                    virtual_host
+                        .redirects
+                        .extend(nginx_return(child, "/", false));
+                }
+                "location" => {
+                    let location = Location::from(child);
+                    if let Some(proxy_pass) = &location.proxy_pass {
+                        virtual_host.proxy_passes.push(proxy_pass.clone());
+                    }
+                    // `return` answers before anything else in the location
+                    if let Some(redirect) = location.redirect {
+                        virtual_host.redirects.push(redirect);
+                        continue;
+                    }
+                    virtual_host.locations.push(location);
+                }
+                _ => {}
+            }
+        }
+        // the server's timeout applies to the locations not setting their own
+        if let Some(timeout) = connect_timeout {
+            let location_proxy_passes = virtual_host
+                .locations
+                .iter_mut()
+                .filter_map(|location| location.proxy_pass.as_mut());
+            for proxy_pass in virtual_host
+                .proxy_passes
+                .iter_mut()
+                .chain(location_proxy_passes)
+            {
+                proxy_pass
+                    .params
+                    .entry("connectiontimeout".to_string())
+                    .or_insert_with(|| timeout.clone());
+            }
+        }
+        virtual_host.host = virtual_host
+            .listen
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "*:80".to_string());
+        virtual_host
+    }
+}
+
+impl From<&Directive<Nginx>> for Location {
+    fn from(directive: &Directive<Nginx>) -> Self {
+        // `location [ = | ~ | ~* | ^~ ] uri { ... }`
+        let (modifier, path) = match directive.args.as_slice() {
+            [modifier, path, ..] => (modifier.as_str(), path.to_string()),
+            [path] => ("", path.to_string()),
+            [] => ("", String::new()),
+        };
+        // exact matches become anchored regexes, matched before the others
+        let (path, regex, precedence) = match modifier {
+            "~" => (path, true, 0),
+            "~*" => (format!("(?i){path}"), true, 0),
+            "=" => (format!("^{}$", regex_escape(&path)), true, EXACT_PRIORITY),
+            "^~" => (path, false, PREFERRED_PRIORITY),
+            _ => (path, false, 0),
+        };
+        let mut location = Location {
+            path,
+            regex,
+            precedence,
+            ..Default::default()
+        };
+        let mut connect_timeout = None;
+        for child in directive.children.iter().flatten() {
+            match child.name.as_str() {
+                "proxy_pass" => {
+                    let mut proxy_pass = ProxyPass::from_args(
+                        Some(location.path.clone()),
+                        &child.args,
+                        location.regex,
+                    );
+                    proxy_pass.precedence = location.precedence;
+                    location.proxy_pass = Some(proxy_pass);
+                }
+                "limit_except" => {
+                    location.allow_methods = child.args.clone();
+                }
+                "add_header" | "proxy_set_header" => {
+                    location.headers.extend(nginx_header(child));
+                }
+                "auth_basic" if child.args.first().is_some_and(|realm| realm != "off") => {
+                    location.auth.set("AuthType", &["Basic".to_string()]);
+                    location.auth.set("AuthName", &child.args);
+                    location.access_rules.push(AccessRule {
+                        directive: "Require".to_string(),
+                        args: vec!["valid-user".to_string()],
+                        children: vec![],
+                    });
+                }
+                "auth_basic_user_file" => {
+                    location.auth.set("AuthUserFile", &child.args);
+                }
+                "proxy_connect_timeout" => {
+                    connect_timeout = child.args.first().cloned();
+                }
+                "return" => {
+                    location.redirect = nginx_return(child, &location.path, location.regex);
+                }
+                _ => {}
+            }
+        }
+        if let (Some(proxy_pass), Some(timeout)) = (&mut location.proxy_pass, connect_timeout) {
+            proxy_pass
+                .params
+                .insert("connectiontimeout".to_string(), timeout);
+        }
+        location
+    }
+}
+
+/// `upstream name { server address [weight=n] [backup] [down]; }` as a
+/// balancer. nginx picks the scheme in `proxy_pass`, the members start out
+/// as http and [`VirtualHost::link_upstreams`] switches them when needed.
+fn nginx_upstream(directive: &Directive<Nginx>) -> Option<Balancer> {
+    let name = directive.args.first()?;
+    let mut balancer = Balancer {
+        name: name.to_string(),
+        upstream: true,
+        ..Default::default()
+    };
+    for child in directive.children.iter().flatten() {
+        match child.name.as_str() {
+            "server" => {
+                let Some(address) = child.args.first() else {
+                    continue;
+                };
+                if address.starts_with("unix:") {
+                    warn!("upstream {name}: traefik can't proxy to {address}");
+                    continue;
+                }
+                let mut params = BTreeMap::new();
+                for arg in &child.args[1..] {
+                    match arg.split_once('=') {
+                        Some(("weight", weight)) => {
+                            params.insert("loadfactor".to_string(), weight.to_string());
+                        }
+                        None if arg == "backup" => {
+                            params.insert("status".to_string(), "+H".to_string());
+                        }
+                        None if arg == "down" => {
+                            params.insert("status".to_string(), "+D".to_string());
+                        }
+                        _ => debug!("upstream {name}: ignoring {arg} of {address}"),
+                    }
+                }
+                balancer.members.push(BalancerMember {
+                    url: format!("http://{address}"),
+                    params,
+                });
+            }
+            "least_conn" => {
+                balancer
+                    .params
+                    .insert("lbmethod".to_string(), "bybusyness".to_string());
+            }
+            "ip_hash" | "hash" | "random" => warn!(
+                "upstream {name}: {} isn't translated, traefik balances by weighted round robin",
+                child.name
+            ),
+            _ => {}
+        }
+    }
+    Some(balancer)
+}
+
+impl VirtualHost {
+    /// Points `proxy_pass http://name` at the `upstream name` block, as
+    /// [`VirtualHost::link_balancers`] looks for `balancer://name`.
+    pub(super) fn link_upstreams(&mut self, balancers: &[Balancer]) {
+        let location_proxy_passes = self
+            .locations
+            .iter_mut()
+            .filter_map(|location| location.proxy_pass.as_mut());
+        for proxy_pass in self.proxy_passes.iter_mut().chain(location_proxy_passes) {
+            let Some((scheme, rest)) = proxy_pass.url.split_once("://") else {
+                continue;
+            };
+            let name = rest.split('/').next().unwrap_or_default();
+            let Some(balancer) = balancers
+                .iter()
+                .find(|balancer| balancer.upstream && balancer.name == name)
+            else {
+                continue;
+            };
+            let path = &rest[name.len()..];
+            proxy_pass.url = match scheme {
+                "http" => format!("balancer://{name}{path}"),
+                // the host gets a copy reaching the members over https
+                "https" => {
+                    let copy = format!("{name}-https");
+                    if !self.balancers.iter().any(|own| own.name == copy) {
+                        let mut balancer = balancer.clone();
+                        balancer.name = copy.clone();
+                        for member in &mut balancer.members {
+                            member.url = member.url.replacen("http://", "https://", 1);
+                        }
+                        self.balancers.push(balancer);
+                    }
+                    format!("balancer://{copy}{path}")
+                }
+                _ => continue,
+            };
+        }
+    }
+}
+
+/// `return code [URL]` or `return URL` in a server or location, as a
+/// redirect of `path`. nginx sends the URL as it is unless it ends in
+/// `$request_uri`; `$scheme` and `$host` are the only other variables
+/// traefik can fill in.
+fn nginx_return(directive: &Directive<Nginx>, path: &str, regex: bool) -> Option<Redirect> {
+    let mut args = directive.args.iter();
+    let first = args.next()?;
+    let (status, target) = match first.parse::<u16>() {
+        Ok(status) => (status, args.next()),
+        Err(_) => (302, Some(first)),
+    };
+    let Some(target) = target.filter(|_| (300..400).contains(&status)) else {
+        warn!(
+            "return {} isn't translated, traefik only redirects",
+            directive.args.join(" ")
+        );
+        return None;
+    };
+    let (target, keeps_uri) = match target.strip_suffix("$request_uri") {
+        Some(base) => (base, true),
+        None => (target.as_str(), false),
+    };
+    let unknown = target.split('$').skip(1).any(|rest| {
+        !rest.starts_with(|c: char| c.is_ascii_digit())
+            && !NGINX_VARIABLES
+                .iter()
+                .any(|(variable, _)| rest.starts_with(&variable[1..]))
+    });
+    if unknown || (keeps_uri && regex) {
+        warn!(
+            "return {} isn't translated, traefik can't fill in its variables",
+            directive.args.join(" ")
+        );
+        return None;
+    }
+    Some(if keeps_uri {
+        // a prefix redirect appends the rest of the path and the query
+        Redirect {
+            path: path.to_string(),
+            regex: false,
+            status,
+            target: Some(format!("{target}{}", path.trim_end_matches('/'))),
+        }
+    } else {
+        Redirect {
+            path: if regex {
+                path.to_string()
+            } else {
+                format!("^{}", regex_escape(path))
+            },
+            regex: true,
+            status,
+            target: Some(target.to_string()),
+        }
+    })
+}
+
+/// The `location` modifier for a path, `^~` keeps a preferred prefix ahead of
+/// the regex locations.
+fn location_modifier(regex: bool, precedence: i64) -> &'static str {
+    match (regex, precedence > 0) {
+        (true, _) => "~ ",
+        (false, true) => "^~ ",
+        (false, false) => "",
+    }
+}
+
+/// `rewrite regex replacement [last | break | redirect | permanent]`.
+fn nginx_rewrite(directive: &Directive<Nginx>) -> Option<RewriteRule> {
+    let [pattern, substitution, ..] = directive.args.as_slice() else {
+        return None;
+    };
+    let mut flags = RewriteFlags::default();
+    match directive.args.get(2).map(String::as_str) {
+        Some("last") => flags.last = true,
+        Some("break") => flags.end = true,
+        Some("redirect") => flags.redirect = Some(302),
+        Some("permanent") => flags.redirect = Some(301),
+        _ => {}
+    }
+    Some(RewriteRule {
+        pattern: pattern.to_string(),
+        substitution: substitution.to_string(),
+        flags,
+        ..Default::default()
+    })
+}
+
+impl RewriteRule {
+    /// Renders the rule as an nginx `rewrite`, or as a comment when it
+    /// depends on something nginx's `rewrite` can't express.
+    fn to_nginx_rewrite(&self) -> String {
+        let flags = &self.flags;
+        let supported = self.conditions.is_empty()
+            && !self.negated
+            && self.substitution != "-"
+            && !(flags.proxy || flags.forbidden || flags.gone);
+        if !supported {
+            return format!("# {self} is not translated");
+        }
+        let pattern = if flags.nocase {
+            format!("(?i){}", self.pattern)
+        } else {
+            self.pattern.clone()
+        };
+        let flag = match flags.redirect {
+            Some(301 | 308) => " permanent",
+            Some(_) => " redirect",
+            None if flags.end => " break",
+            None if flags.last => " last",
+            None => "",
+        };
+        // nginx keeps the query string unless the replacement ends in `?`
+        let substitution = if flags.qsdiscard && !self.substitution.contains('?') {
+            format!("{}?", self.substitution)
+        } else {
+            self.substitution.clone()
+        };
+        format!(
+            "rewrite {} {}{flag};",
+            quote(&pattern),
+            quote(&substitution)
+        )
+    }
+}
+
+/// `add_header name value [always]` or `proxy_set_header name value`.
+/// Values with nginx variables such as `$host` are skipped, traefik would
+/// send them as they are.
+fn nginx_header(directive: &Directive<Nginx>) -> Option<Header> {
+    let [name, value, ..] = directive.args.as_slice() else {
+        return None;
+    };
+    if value.contains('$') {
+        warn!(
+            "{} {name} {value} isn't translated: traefik can't compute header values",
+            directive.name
+        );
+        return None;
+    }
+    let kind = if directive.name == "proxy_set_header" {
+        HeaderKind::Request
+    } else {
+        HeaderKind::Response
+    };
+    let mut header = Header::set(kind, name, value);
+    header.always = directive.args.get(2).is_some_and(|arg| arg == "always");
+    Some(header)
+}
+
+impl Header {
+    /// Renders the header as `add_header` or `proxy_set_header`, or as a
+    /// comment when nginx can't express it.
+    fn to_nginx_directive(&self) -> String {
+        let value = self.value.as_deref().unwrap_or_default();
+        let supported = self.condition.is_none()
+            && !value.starts_with("expr=")
+            && !value.contains("%{")
+            && !matches!(
+                self.action,
+                HeaderAction::Edit { .. } | HeaderAction::Echo | HeaderAction::Note
+            );
+        if !supported {
+            return format!("# {self} is not translated");
+        }
+        match (self.kind, &self.action) {
+            (HeaderKind::Request, HeaderAction::Unset) => {
+                format!("proxy_set_header {} \"\";", self.name)
+            }
+            (HeaderKind::Request, _) => {
+                format!("proxy_set_header {} {};", self.name, quote(value))
+            }
+            (HeaderKind::Response, HeaderAction::Unset) => {
+                format!("proxy_hide_header {};", self.name)
+            }
+            (HeaderKind::Response, _) => {
+                let always = if self.always { " always" } else { "" };
+                format!("add_header {} {}{always};", self.name, quote(value))
+            }
+        }
+    }
+}
+
+impl ProxyPass {
+    /// The timeouts given as `ProxyPass` parameters, as location
+    /// directives.
+    fn to_nginx_timeouts(&self) -> String {
+        let mut config = String::new();
+        let timeouts = [
+            ("connectiontimeout", "proxy_connect_timeout"),
+            ("timeout", "proxy_read_timeout"),
+        ];
+        for (param, directive) in timeouts {
+            if let Some(timeout) = self.params.get(param).and_then(|v| go_duration(v)) {
+                config.push_str(&format!("        {directive} {timeout};\n"));
+            }
+        }
+        config
+    }
+}
+
+impl Redirect {
+    /// Renders the redirect as an nginx `rewrite`, carrying the rest of the
+    /// path over like mod_alias does.
+    fn to_nginx_rewrite(&self) -> String {
+        let pattern = if self.regex {
+            self.path.clone()
+        } else {
+            format!("^{}(/.*)?$", regex_escape(self.path.trim_end_matches('/')))
+        };
+        let Some(target) = &self.target else {
+            return format!(
+                "location ~ {} {{ return {}; }}",
+                quote(&pattern),
+                self.status
+            );
+        };
+        let flag = match self.status {
+            301 | 308 => "permanent",
+            302 | 307 => "redirect",
+            status => return format!("# Redirect {status} {} is not translated", self.path),
+        };
+        // a target with its own query string replaces the request's
+        let target = if self.regex || target.contains('?') {
+            target.clone()
+        } else {
+            format!("{}$1", target.trim_end_matches('/'))
+        };
+        format!("rewrite {} {} {flag};", quote(&pattern), quote(&target))
+    }
+}
+
+/// Turns an nginx `listen` argument into the `address:port` form used by
+/// Apache `<VirtualHost>` declarations.
+fn normalize_listen(address: &str) -> String {
+    if address.starts_with("unix:") {
+        return address.to_string();
+    }
+    if address.chars().all(|c| c.is_ascii_digit()) {
+        return format!("*:{address}");
+    }
+    if let Some(rest) = address.strip_prefix('[') {
+        // IPv6, e.g. `[::]:443` or `[::1]`
+        return match rest.split_once(']') {
+            Some((_, "")) => format!("{address}:80"),
+            _ => address.to_string(),
+        };
+    }
+    if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{address}:80")
+    }
+}
+
+impl ProxyConfig {
+    /// Renders the balancers the hosts use as nginx `upstream {}` blocks,
+    /// once each as nginx refuses a name declared twice.
+    pub fn to_nginx_upstreams(&self) -> String {
+        let mut config = String::new();
+        let mut seen = HashSet::new();
+        for balancer in self
+            .virtual_hosts
+            .iter()
+            .flat_map(|virtual_host| &virtual_host.balancers)
+        {
+            if !seen.insert(&balancer.name) {
+                continue;
+            }
+            if let Some(upstream) = balancer.to_nginx_upstream() {
+                config.push_str(&upstream);
+                config.push('\n');
+            }
+        }
+        config
+    }
+}
+
+impl VirtualHost {
+    /// Renders the virtual host as an nginx `server {}` block. The upstreams
+    /// of its balancers come from [`ProxyConfig::to_nginx_upstreams`].
+    pub fn to_nginx_config(&self) -> String {
+        let mut config = String::new();
+        if self.server_name.is_empty() {
+            return config;
+        }
+        config.push_str("server {\n");
+
+        let listen = if self.listen.is_empty() {
+            vec![self.host.clone()]
+        } else {
+            self.listen.clone()
+        };
+        for address in listen {
+            // Apache's `_default_` host is nginx's `default_server`
+            let (address, default_server) = match address.strip_prefix("_default_") {
+                Some(port) => (port.trim_start_matches(':'), " default_server"),
+                None => (address.as_str(), ""),
+            };
+            let address = match address.strip_prefix("*:") {
+                Some(port) => port,
+                None if address.is_empty() => "80",
+                None => address,
+            };
+            let ssl = if self.ssl_config.enabled { " ssl" } else { "" };
+            config.push_str(&format!("    listen {address}{default_server}{ssl};\n"));
+        }
+
+        let server_names = std::iter::once(self.name())
+            .chain(self.server_aliases.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ");
+        config.push_str(&format!("    server_name {server_names};\n"));
+
+        if !self.document_root.is_empty() {
+            config.push_str(&format!("    root {};\n", quote(&self.document_root)));
+        }
+        if !self.custom_log.is_empty() {
+            config.push_str(&format!("    access_log {};\n", quote(&self.custom_log)));
+        }
+
+        let ssl = &self.ssl_config;
+        if ssl.enabled {
+            config.push('\n');
+            if let Some(certificate) = ssl.certificate() {
+                // nginx has no separate chain directive, it reads the chain
+                // from the certificate file
+                if let Some(chain_file) = &ssl.chain_file {
+                    config.push_str(&format!(
+                        "    # append {chain_file} to the certificate, or use {}\n",
+                        ssl.combined_file().unwrap_or_default()
+                    ));
+                }
+                config.push_str(&format!(
+                    "    ssl_certificate {};\n",
+                    quote(&ssl.certificate_file)
+                ));
+                config.push_str(&format!(
+                    "    ssl_certificate_key {};\n",
+                    quote(&certificate.key_file)
+                ));
+            }
+            let protocols = ssl.enabled_protocols();
+            if !protocols.is_empty() {
+                config.push_str(&format!("    ssl_protocols {};\n", protocols.join(" ")));
+            }
+            if let Some(ciphers) = &ssl.ciphers {
+                config.push_str(&format!("    ssl_ciphers {};\n", quote(ciphers)));
+            }
+            if let Some(honor_cipher_order) = ssl.honor_cipher_order {
+                let value = if honor_cipher_order { "on" } else { "off" };
+                config.push_str(&format!("    ssl_prefer_server_ciphers {value};\n"));
+            }
+        }
+
+        let mut timeouts = vec![];
+        if let Some(timeout) = self.timeouts.response() {
+            timeouts.push(format!("proxy_read_timeout {timeout};"));
+            timeouts.push(format!("proxy_send_timeout {timeout};"));
+        }
+        if let Some(timeout) = &self.timeouts.keep_alive_timeout {
+            timeouts.push(format!("keepalive_timeout {timeout};"));
+        }
+        if !timeouts.is_empty() {
+            config.push('\n');
+            for directive in timeouts {
+                config.push_str(&format!("    {directive}\n"));
+            }
+        }
+
+        let ssl_proxy = &self.ssl_proxy;
+        let mut proxy_ssl = vec![];
+        if ssl_proxy.verifies() == Some(true) {
+            proxy_ssl.push("proxy_ssl_verify on;".to_string());
+        }
+        if let Some(file) = &ssl_proxy.ca_certificate_file {
+            proxy_ssl.push(format!("proxy_ssl_trusted_certificate {};", quote(file)));
+        }
+        if let Some(file) = &ssl_proxy.machine_certificate_file {
+            // the certificate and its key share Apache's file
+            proxy_ssl.push(format!("proxy_ssl_certificate {};", quote(file)));
+            proxy_ssl.push(format!("proxy_ssl_certificate_key {};", quote(file)));
+        }
+        if let Some(server_name) = &ssl_proxy.server_name {
+            proxy_ssl.push(format!("proxy_ssl_name {};", quote(server_name)));
+        }
+        if !proxy_ssl.is_empty() {
+            config.push('\n');
+            for directive in proxy_ssl {
+                config.push_str(&format!("    {directive}\n"));
+            }
+        }
+
+        // `<Location />` is rendered as a location of its own
+        if let Some(section) = self
+            .host_access_section()
+            .filter(|section| section.kind == LocationKind::Directory)
+        {
+            let directives = nginx_access(section, &section.auth);
+            if !directives.is_empty() {
+                config.push('\n');
+                for directive in directives {
+                    config.push_str(&format!("    {directive}\n"));
+                }
+            }
+        }
+
+        if !self.headers.is_empty() {
+            config.push('\n');
+            for header in &self.headers {
+                config.push_str(&format!("    {}\n", header.to_nginx_directive()));
+            }
+        }
+
+        if !self.rewrite_rules.is_empty() {
+            config.push('\n');
+            for rule in &self.rewrite_rules {
+                config.push_str(&format!("    {}\n", rule.to_nginx_rewrite()));
+            }
+        }
+
+        if !self.redirects.is_empty() {
+            config.push('\n');
+            for redirect in &self.redirects {
+                config.push_str(&format!("    {}\n", redirect.to_nginx_rewrite()));
+            }
+        }
+
+        for proxy_pass in &self.proxy_passes {
+            if self
+                .locations
+                .iter()
+                .any(|location| location.path == proxy_pass.path)
+            {
+                continue;
+            }
+            config.push('\n');
+            let modifier = location_modifier(proxy_pass.regex, proxy_pass.precedence);
+            config.push_str(&format!(
+                "    location {modifier}{} {{\n",
+                quote(&proxy_pass.path)
+            ));
+            if !proxy_pass.excluded {
+                config.push_str(&format!(
+                    "        proxy_pass {};\n",
+                    nginx_proxy_url(&proxy_pass.url)
+                ));
+                config.push_str(&proxy_pass.to_nginx_timeouts());
+            }
+            config.push_str("    }\n");
+        }
+
+        for location in &self.locations {
+            // directories and files map onto the document root, not onto
+            // request paths
+            if location.kind != LocationKind::Location {
+                continue;
+            }
+            config.push('\n');
+            let modifier = location_modifier(location.regex, location.precedence);
+            config.push_str(&format!(
+                "    location {modifier}{} {{\n",
+                quote(&location.path)
+            ));
+            // a `ProxyPass` outside of the section still proxies its path,
+            // a location block without `proxy_pass` would serve files
+            let proxy_pass = location.proxy_pass.clone().or_else(|| {
+                self.proxy_passes
+                    .iter()
+                    .filter(|proxy_pass| !proxy_pass.regex && !proxy_pass.excluded)
+                    .filter(|proxy_pass| {
+                        !location.regex && location.path.starts_with(&proxy_pass.path)
+                    })
+                    .max_by_key(|proxy_pass| proxy_pass.path.len())
+                    .map(|proxy_pass| ProxyPass {
+                        url: format!(
+                            "{}{}",
+                            proxy_pass.url,
+                            &location.path[proxy_pass.path.len()..]
+                        ),
+                        ..proxy_pass.clone()
+                    })
+            });
+            if let Some(proxy_pass) = proxy_pass {
+                config.push_str(&format!(
+                    "        proxy_pass {};\n",
+                    nginx_proxy_url(&proxy_pass.url)
+                ));
+                config.push_str(&proxy_pass.to_nginx_timeouts());
+            }
+            for directive in nginx_access(location, &self.section_auth(location)) {
+                config.push_str(&format!("        {directive}\n"));
+            }
+            for header in &location.headers {
+                config.push_str(&format!("        {}\n", header.to_nginx_directive()));
+            }
+            if !location.allow_methods.is_empty() {
+                config.push_str(&format!(
+                    "        limit_except {} {{\n            deny all;\n        }}\n",
+                    location.allow_methods.join(" ")
+                ));
+            }
+            config.push_str("    }\n");
+        }
+
+        config.push_str("}\n");
+        config
+    }
+}
+
+impl Balancer {
+    /// Renders the balancer as an nginx `upstream {}` block named like the
+    /// balancer, or none when it has no server nginx can reach.
+    fn to_nginx_upstream(&self) -> Option<String> {
+        let mut config = format!("upstream {} {{\n", self.name);
+        let mut servers = 0;
+        let lbmethod = self.params.get("lbmethod").map(|m| m.to_lowercase());
+        if lbmethod.as_deref() == Some("bybusyness") {
+            config.push_str("    least_conn;\n");
+        }
+        let sticky = self.params.get("stickysession");
+        if let Some(cookie) = sticky {
+            let cookie = cookie.split('|').next().unwrap_or_default();
+            config.push_str(&format!("    hash $cookie_{cookie} consistent;\n"));
+        }
+        for member in &self.members {
+            let Some(upstream) = super::Upstream::parse(&member.url) else {
+                continue;
+            };
+            let mut server = format!("    server {}:{}", upstream.host, upstream.port);
+            if let Some(weight) = member.params.get("loadfactor") {
+                server.push_str(&format!(" weight={weight}"));
+            }
+            let status = member.status();
+            if status.contains(&'D') || status.contains(&'S') {
+                server.push_str(" down");
+            } else if status.contains(&'H') && sticky.is_none() {
+                // nginx doesn't allow backup servers with `hash`
+                server.push_str(" backup");
+            }
+            config.push_str(&server);
+            config.push_str(";\n");
+            servers += 1;
+        }
+        if servers == 0 {
+            warn!(
+                "balancer://{} has no members, its upstream is left out",
+                self.name
+            );
+            return None;
+        }
+        config.push_str("}\n");
+        Some(config)
+    }
+}
+
+/// `allow`, `deny` and `auth_basic` for the access rules of a section, or
+/// a comment for what nginx can't express.
+fn nginx_access(section: &Location, auth: &AuthConfig) -> Vec<String> {
+    if section.access_rules.is_empty() {
+        return vec![];
+    }
+    let requirements = match access::evaluate(&section.access_rules) {
+        Ok(requirements) => requirements,
+        Err(reason) => {
+            return vec![
+                format!("# access rules not translated: {reason}"),
+                "deny all;".to_string(),
+            ]
+        }
+    };
+    let mut directives = match requirements.access {
+        Access::Granted => vec![],
+        Access::Denied => vec!["deny all;".to_string()],
+        Access::Sources(sources) => sources
+            .iter()
+            .map(|source| format!("allow {source};"))
+            .chain(std::iter::once("deny all;".to_string()))
+            .collect(),
+    };
+    if let (Some(login), Some(provider)) = (&requirements.login, auth.provider()) {
+        // closed to everyone until an auth_request to the provider is set up
+        return vec![
+            format!("# {login} checked by {provider} not translated"),
+            "deny all;".to_string(),
+        ];
+    }
+    let basic = auth
+        .auth_type
+        .as_deref()
+        .is_some_and(|auth_type| auth_type.eq_ignore_ascii_case("basic"));
+    match (&requirements.login, &auth.user_file) {
+        (None, _) => {}
+        (Some(login), Some(user_file)) if basic => {
+            // nginx lets in any user of the file
+            if *login != Login::AnyUser {
+                directives.push(format!("# {login} not translated"));
+            }
+            let realm = auth.name.as_deref().unwrap_or("Restricted");
+            directives.push(format!("auth_basic {};", quote(realm)));
+            directives.push(format!("auth_basic_user_file {};", quote(user_file)));
+        }
+        (Some(login), _) => {
+            return vec![format!("# {login} not translated"), "deny all;".to_string()]
+        }
+    }
+    directives
+}
+
+/// `balancer://name/path` becomes `http://name/path`, pointing at the
+/// `upstream` block rendered for the balancer.
+fn nginx_proxy_url(url: &str) -> String {
+    match url.strip_prefix("balancer://") {
+        Some(rest) => format!("http://{rest}"),
+        None => url.to_string(),
+    }
+}
+
+/// Quotes an nginx argument when it contains characters that would otherwise
+/// end or split it.
+fn quote(value: &str) -> String {
+    if value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, ';' | '{' | '}' | '"' | '\''))
+    {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use misc_conf::{apache::Apache, ast::DirectiveTrait};
+
+    use super::*;
+    use crate::cmd::configs::traefik::DynamicConfig;
+
+    fn proxy_config(nginx: &str) -> ProxyConfig {
+        let mut configs = ProxyConfig::default();
+        for directive in Directive::<Nginx>::parse(nginx.as_bytes()).unwrap() {
+            configs.merge(ProxyConfig::from(directive));
+        }
+        configs.inherit_server_config();
+        configs
+    }
+
+    fn traefik_config(nginx: &str) -> DynamicConfig {
+        proxy_config(nginx).to_traefik_config()
+    }
+
+    #[test]
+    fn location_modifiers() {
+        let configs = proxy_config(
+            r"
+server {
+    listen 80;
+    server_name example.com;
+    location = /health { proxy_pass http://app:9000; }
+    location ^~ /static/ { proxy_pass http://app:9001; }
+    location ~* \.png$ { proxy_pass http://app:9002; }
+    location ~ ^/api/ { proxy_pass http://app:9003; }
+    location /docs { proxy_pass http://app:9004; }
+}
+",
+        );
+        let locations = configs.virtual_hosts[0]
+            .locations
+            .iter()
+            .map(|location| (location.path.as_str(), location.regex, location.precedence))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            vec![
+                ("^/health$", true, EXACT_PRIORITY),
+                ("/static/", false, PREFERRED_PRIORITY),
+                (r"(?i)\.png$", true, 0),
+                ("^/api/", true, 0),
+                ("/docs", false, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn short_directives_are_skipped() {
+        let configs = proxy_config(
+            "
+server {
+    listen 443;
+    server_name example.com;
+    root;
+    ssl;
+    ssl_certificate;
+    proxy_ssl_name;
+    rewrite ^/old$;
+    location / { proxy_pass http://app:3000; }
+}
+",
+        );
+        let virtual_host = &configs.virtual_hosts[0];
+        assert!(virtual_host.rewrite_rules.is_empty());
+        assert!(!virtual_host.ssl_config.enabled);
+        assert_eq!(virtual_host.document_root, "");
+    }
+
+    #[test]
+    fn upstreams_become_balancers() {
+        let config = traefik_config(
+            "
+http {
+    upstream pool {
+        server 10.0.0.1:8080 weight=2;
+        server 10.0.0.2:8080 backup;
+        server 10.0.0.3;
+    }
+    server {
+        listen 80;
+        server_name example.com;
+        location / { proxy_pass http://pool; }
+        location /secure/ { proxy_pass https://pool; }
+    }
+}
+",
+        );
+        let servers = |service: &str| {
+            config.http.services[service]
+                .load_balancer
+                .servers
+                .iter()
+                .map(|server| (server.url.clone(), server.weight))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            servers("example-com"),
+            vec![
+                ("http://10.0.0.1:8080".to_string(), Some(2)),
+                ("http://10.0.0.3".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            servers("example-com-secure")[0],
+            ("https://10.0.0.1:8080".to_string(), Some(2))
+        );
+    }
+
+    #[test]
+    fn returns_become_redirects() {
+        let configs = proxy_config(
+            "
+server {
+    listen 80;
+    server_name example.com;
+    return 301 https://$host$request_uri;
+}
+server {
+    listen 443 ssl;
+    server_name example.com;
+    location = /old { return 302 /new; }
+    location /gone { return 410; }
+    location /agent { return 301 https://$http_user_agent; }
+    location / { proxy_pass http://app:3000; }
+}
+",
+        );
+        let redirects = |index: usize| {
+            configs.virtual_hosts[index]
+                .redirects
+                .iter()
+                .map(|redirect| {
+                    (
+                        redirect.path.clone(),
+                        redirect.regex,
+                        redirect.status,
+                        redirect.target.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            redirects(0),
+            vec![(
+                "/".to_string(),
+                false,
+                301,
+                Some("https://$host".to_string())
+            )]
+        );
+        assert_eq!(
+            redirects(1),
+            vec![("^/old$".to_string(), true, 302, Some("/new".to_string()))]
+        );
+
+        // the redirect-only server doesn't proxy anywhere
+        let config = configs.to_traefik_config();
+        let router = &config.http.routers["example-com-80-redirect"];
+        assert_eq!(router.service, "noop@internal");
+        assert!(!config.http.services.contains_key("example-com-80"));
+        let redirect = config.http.middlewares["example-com-80-redirect-root"]
+            .redirect_regex
+            .clone()
+            .unwrap();
+        assert_eq!(redirect.replacement, "https://${2}${3}${4}");
+    }
+
+    #[test]
+    fn connect_timeouts_reach_the_transports() {
+        let config = traefik_config(
+            "
+server {
+    listen 80;
+    server_name example.com;
+    proxy_connect_timeout 5s;
+    location / { proxy_pass http://app:3000; }
+    location /api/ {
+        proxy_pass http://api:3000;
+        proxy_connect_timeout 2;
+    }
+}
+",
+        );
+        let dial_timeout = |router: &str| {
+            let service = &config.http.services[&config.http.routers[router].service];
+            let transport = service.load_balancer.servers_transport.clone().unwrap();
+            config.http.servers_transports[&transport]
+                .forwarding_timeouts
+                .clone()
+                .unwrap()
+                .dial_timeout
+        };
+        assert_eq!(dial_timeout("example-com").as_deref(), Some("5s"));
+        assert_eq!(dial_timeout("example-com-api").as_deref(), Some("2s"));
+    }
+
+    #[test]
+    fn listen_addresses() {
+        let cases = [
+            ("80", "*:80"),
+            ("10.0.0.1", "10.0.0.1:80"),
+            ("10.0.0.1:8080", "10.0.0.1:8080"),
+            ("[::]:443", "[::]:443"),
+            ("[::1]", "[::1]:80"),
+            ("unix:/run/nginx.sock", "unix:/run/nginx.sock"),
+        ];
+        for (address, expected) in cases {
+            assert_eq!(normalize_listen(address), expected, "{address}");
+        }
+    }
+
+    #[test]
+    fn apache_hosts_render_as_servers() {
+        let mut configs = ProxyConfig::default();
+        let apache = r"
+<VirtualHost _default_:443>
+    ServerName www.example.com
+    ServerAlias example.com
+    DocumentRoot /var/www/html
+    SSLEngine on
+    SSLCertificateFile /etc/ssl/www.crt
+    SSLCertificateKeyFile /etc/ssl/www.key
+    SSLProtocol all -SSLv3 -TLSv1 -TLSv1.1
+    ProxyTimeout 60
+    ProxyPass /static !
+    ProxyPass /app/ http://app:3000/
+    <Proxy balancer://pool>
+        BalancerMember http://10.0.0.1:8080 loadfactor=2
+        BalancerMember http://10.0.0.2:8080 status=+H
+    </Proxy>
+    ProxyPass /api/ balancer://pool/
+    Redirect permanent /old https://www.example.com/new
+</VirtualHost>
+<VirtualHost *:80>
+    ServerName www.example.com
+    Redirect / https://www.example.com/
+</VirtualHost>
+";
+        for directive in Directive::<Apache>::parse(apache.as_bytes()).unwrap() {
+            configs.merge(ProxyConfig::from(directive));
+        }
+        configs.inherit_server_config();
+        assert_eq!(
+            configs.to_nginx_upstreams(),
+            "\
+upstream pool {
+    server 10.0.0.1:8080 weight=2;
+    server 10.0.0.2:8080 backup;
+}
+
+"
+        );
+        let servers = configs
+            .virtual_hosts
+            .iter()
+            .map(VirtualHost::to_nginx_config)
+            .collect::<Vec<_>>();
+        // the excluded path is served from the root
+        assert_eq!(
+            servers[0],
+            "\
+server {
+    listen 443 default_server ssl;
+    server_name www.example.com example.com;
+    root /var/www/html;
+
+    ssl_certificate /etc/ssl/www.crt;
+    ssl_certificate_key /etc/ssl/www.key;
+    ssl_protocols TLSv1.2 TLSv1.3;
+
+    proxy_read_timeout 60s;
+    proxy_send_timeout 60s;
+
+    rewrite ^/old(/.*)?$ https://www.example.com/new$1 permanent;
+
+    location /static {
+    }
+
+    location /app/ {
+        proxy_pass http://app:3000/;
+    }
+
+    location /api/ {
+        proxy_pass http://pool/;
+    }
+}
+"
+        );
+        assert_eq!(
+            servers[1],
+            "\
+server {
+    listen 80;
+    server_name www.example.com;
+
+    rewrite ^(/.*)?$ https://www.example.com$1 redirect;
+}
+"
+        );
+    }
+}
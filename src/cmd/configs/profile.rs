@@ -0,0 +1,292 @@
+//! The migration profile, `proxyparser.toml`: defaults for everything the
+//! generated traefik config used to hard-code, and overrides for the hosts
+//! matching a server name or source file glob.
+//!
+//! ```toml
+//! [defaults]
+//! key_prefix = "traefik"
+//! entry_points = ["websecure"]
+//! flush_interval = "100ms"
+//! insecure_skip_verify = true
+//! response_header_timeout = "30s"
+//! idle_conn_timeout = "30s"
+//!
+//! [[hosts]]
+//! host = "*.internal.example.com"
+//! entry_points = ["internal"]
+//! middlewares = ["internal-only"]
+//! tls_options = "modern"
+//! forward_auth_address = "http://auth.internal:4181/{provider}"
+//! ```
+
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{registry::HostMatcher, VirtualHost};
+use crate::error::{ParserError, ParserResult};
+
+/// The file looked up in the working directory when `--profile` isn't
+/// given.
+pub const DEFAULT_PROFILE: &str = "proxyparser.toml";
+
+/// The `forwardAuth` address until the profile gives one. The `.invalid`
+/// domain never resolves, so the protected paths refuse every request
+/// instead of being open.
+pub const FORWARD_AUTH_STUB: &str = "http://forward-auth.invalid/{provider}";
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub defaults: HostSettings,
+    #[serde(default)]
+    pub hosts: Vec<HostOverride>,
+}
+
+/// Settings for the hosts matching `matcher`, applied over the defaults in
+/// the order they are declared.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct HostOverride {
+    #[serde(flatten)]
+    pub matcher: HostMatcher,
+    #[serde(flatten)]
+    pub settings: HostSettings,
+    /// The keys left over by the fields above, reported by `Profile::load`
+    /// as `deny_unknown_fields` doesn't work with `flatten`.
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+/// The settings as written in the profile, where anything left out keeps
+/// its previous value.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HostSettings {
+    /// Where the keys are written in etcd. Traefik reads a single root
+    /// key, so it can only be set in `[defaults]`.
+    pub key_prefix: Option<String>,
+    pub entry_points: Option<Vec<String>>,
+    /// Entry points of plain http hosts that only redirect.
+    pub redirect_entry_points: Option<Vec<String>>,
+    /// Replaces the middlewares attached by the registry.
+    pub middlewares: Option<Vec<String>>,
+    pub flush_interval: Option<String>,
+    pub insecure_skip_verify: Option<bool>,
+    pub dial_timeout: Option<String>,
+    pub response_header_timeout: Option<String>,
+    pub idle_conn_timeout: Option<String>,
+    /// The `tls.options` of the routers.
+    pub tls_options: Option<String>,
+    pub cert_resolver: Option<String>,
+    /// Where `forwardAuth` sends the requests of sections protected by
+    /// LDAP, OpenID Connect, Shibboleth or another provider traefik lacks;
+    /// `{provider}` and `{host}` are replaced.
+    pub forward_auth_address: Option<String>,
+    pub forward_auth_trust_forward_header: Option<bool>,
+    /// The headers of the auth response passed on to the backend.
+    pub forward_auth_response_headers: Option<Vec<String>>,
+}
+
+/// The settings a host is rendered with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Settings {
+    pub key_prefix: String,
+    pub entry_points: Vec<String>,
+    pub redirect_entry_points: Vec<String>,
+    pub middlewares: Option<Vec<String>>,
+    pub flush_interval: String,
+    pub insecure_skip_verify: bool,
+    pub dial_timeout: Option<String>,
+    pub response_header_timeout: Option<String>,
+    pub idle_conn_timeout: Option<String>,
+    pub tls_options: Option<String>,
+    pub cert_resolver: Option<String>,
+    pub forward_auth_address: String,
+    pub forward_auth_trust_forward_header: Option<bool>,
+    pub forward_auth_response_headers: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            key_prefix: "traefik".to_string(),
+            entry_points: vec!["websecure".to_string()],
+            redirect_entry_points: vec!["web".to_string()],
+            middlewares: None,
+            flush_interval: "100ms".to_string(),
+            insecure_skip_verify: true,
+            dial_timeout: None,
+            response_header_timeout: Some("30s".to_string()),
+            idle_conn_timeout: Some("30s".to_string()),
+            tls_options: None,
+            cert_resolver: None,
+            forward_auth_address: FORWARD_AUTH_STUB.to_string(),
+            forward_auth_trust_forward_header: None,
+            forward_auth_response_headers: vec!["X-Forwarded-User".to_string()],
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, settings: &HostSettings) {
+        let settings = settings.clone();
+        if let Some(key_prefix) = settings.key_prefix {
+            self.key_prefix = key_prefix;
+        }
+        if let Some(entry_points) = settings.entry_points {
+            self.entry_points = entry_points;
+        }
+        if let Some(entry_points) = settings.redirect_entry_points {
+            self.redirect_entry_points = entry_points;
+        }
+        if let Some(flush_interval) = settings.flush_interval {
+            self.flush_interval = flush_interval;
+        }
+        if let Some(insecure_skip_verify) = settings.insecure_skip_verify {
+            self.insecure_skip_verify = insecure_skip_verify;
+        }
+        if let Some(address) = settings.forward_auth_address {
+            self.forward_auth_address = address;
+        }
+        if let Some(headers) = settings.forward_auth_response_headers {
+            self.forward_auth_response_headers = headers;
+        }
+        self.middlewares = settings.middlewares.or(self.middlewares.take());
+        self.dial_timeout = settings.dial_timeout.or(self.dial_timeout.take());
+        self.response_header_timeout = settings
+            .response_header_timeout
+            .or(self.response_header_timeout.take());
+        self.idle_conn_timeout = settings.idle_conn_timeout.or(self.idle_conn_timeout.take());
+        self.tls_options = settings.tls_options.or(self.tls_options.take());
+        self.cert_resolver = settings.cert_resolver.or(self.cert_resolver.take());
+        self.forward_auth_trust_forward_header = settings
+            .forward_auth_trust_forward_header
+            .or(self.forward_auth_trust_forward_header);
+    }
+}
+
+impl Profile {
+    pub fn load(path: &Path) -> ParserResult<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let invalid = |err: String| ParserError::Config(format!("{}: {err}", path.display()));
+        let profile: Self = toml::from_str(&data).map_err(|e| invalid(e.to_string()))?;
+        for host in &profile.hosts {
+            host.matcher.validate().map_err(invalid)?;
+            if let Some(key) = host.unknown.keys().next() {
+                return Err(invalid(format!("unknown key `{key}` in [[hosts]]")));
+            }
+            if host.settings.key_prefix.is_some() {
+                return Err(invalid(
+                    "key_prefix can only be set in [defaults]".to_string(),
+                ));
+            }
+        }
+        Ok(profile)
+    }
+
+    /// The settings shared by every host, used for what doesn't belong to
+    /// one such as the registry's middlewares.
+    pub fn defaults(&self) -> Settings {
+        let mut settings = Settings::default();
+        settings.apply(&self.defaults);
+        settings
+    }
+
+    pub fn settings_for(&self, virtual_host: &VirtualHost) -> Settings {
+        let mut settings = self.defaults();
+        for host in &self.hosts {
+            if host.matcher.matches(virtual_host) {
+                settings.apply(&host.settings);
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn load(profile: &str) -> ParserResult<Profile> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_PROFILE);
+        std::fs::write(&path, profile).unwrap();
+        Profile::load(&path)
+    }
+
+    fn host(name: &str, source: &str) -> VirtualHost {
+        VirtualHost::builder()
+            .server_name(name.to_string())
+            .source(PathBuf::from(source))
+            .build()
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        let cases = [
+            ("[defaults]\nkeyprefix = \"t\"", "unknown field `keyprefix`"),
+            (
+                "[[hosts]]\nhost = \"a\"\nentrypoints = []",
+                "unknown key `entrypoints` in [[hosts]]",
+            ),
+            (
+                "[[hosts]]\nhost = \"a\"\nkey_prefix = \"t\"",
+                "key_prefix can only be set in [defaults]",
+            ),
+            ("[[hosts]]\nhost = \"[a\"", "[a"),
+            ("[host]\nhost = \"a\"", "unknown field `host`"),
+        ];
+        for (profile, expected) in cases {
+            let err = load(profile).unwrap_err().to_string();
+            assert!(err.contains(expected), "{profile}: {err}");
+        }
+    }
+
+    #[test]
+    fn overrides_apply_in_order() {
+        let profile = load(
+            r#"
+[defaults]
+key_prefix = "edge"
+entry_points = ["websecure"]
+response_header_timeout = "60s"
+
+[[hosts]]
+host = "*.internal.example.com"
+entry_points = ["internal"]
+middlewares = ["internal-only"]
+tls_options = "modern"
+
+[[hosts]]
+file = "legacy/*.conf"
+tls_options = "old"
+insecure_skip_verify = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(profile.defaults().key_prefix, "edge");
+
+        let public = profile.settings_for(&host("www.example.com", "/etc/httpd/sites/www.conf"));
+        assert_eq!(public, profile.defaults());
+        assert_eq!(public.response_header_timeout.as_deref(), Some("60s"));
+        assert_eq!(public.idle_conn_timeout.as_deref(), Some("30s"));
+
+        let internal = profile.settings_for(&host(
+            "wiki.internal.example.com",
+            "/etc/httpd/legacy/wiki.conf",
+        ));
+        assert_eq!(internal.key_prefix, "edge");
+        assert_eq!(internal.entry_points, vec!["internal"]);
+        assert_eq!(
+            internal.middlewares,
+            Some(vec!["internal-only".to_string()])
+        );
+        // the later override wins
+        assert_eq!(internal.tls_options.as_deref(), Some("old"));
+        assert!(!internal.insecure_skip_verify);
+        assert_eq!(internal.response_header_timeout.as_deref(), Some("60s"));
+    }
+}
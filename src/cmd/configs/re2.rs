@@ -0,0 +1,389 @@
+//! Checks Apache's PCRE patterns against Go's RE2, which Traefik compiles
+//! router rules and regex middlewares with. RE2 has no backtracking, so
+//! lookarounds and backreferences can't be expressed at all, while a few
+//! PCRE spellings can be rewritten into RE2 ones.
+
+use serde::{Deserialize, Serialize};
+
+use super::{LocationKind, VirtualHost};
+
+/// Go rejects repeat counts above this.
+const MAX_REPEAT: u32 = 1000;
+
+/// How a PCRE pattern fares under RE2.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "status")]
+pub enum Compatibility {
+    Compatible,
+    /// RE2 accepts the pattern once rewritten into `pattern`.
+    Convertible {
+        pattern: String,
+        changes: Vec<String>,
+    },
+    Unsupported {
+        reason: String,
+    },
+}
+
+impl Compatibility {
+    /// A short name for the classification.
+    pub fn status(&self) -> &'static str {
+        match self {
+            Compatibility::Compatible => "compatible",
+            Compatibility::Convertible { .. } => "convertible",
+            Compatibility::Unsupported { .. } => "unsupported",
+        }
+    }
+}
+
+/// A regex found in a virtual host along with where it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegexCheck {
+    pub source: String,
+    pub pattern: String,
+    pub compatibility: Compatibility,
+}
+
+/// The pattern to hand to Traefik, or why there is none.
+pub fn to_re2(pattern: &str) -> Result<String, String> {
+    match check(pattern) {
+        Compatibility::Compatible => Ok(pattern.to_string()),
+        Compatibility::Convertible { pattern, .. } => Ok(pattern),
+        Compatibility::Unsupported { reason } => Err(reason),
+    }
+}
+
+/// Classifies a PCRE pattern.
+pub fn check(pattern: &str) -> Compatibility {
+    let mut out = String::new();
+    let mut changes: Vec<String> = vec![];
+    let mut change = |description: &str| {
+        if !changes.iter().any(|c| c == description) {
+            changes.push(description.to_string());
+        }
+    };
+    let unsupported = |reason: &str| Compatibility::Unsupported {
+        reason: reason.to_string(),
+    };
+
+    let chars = pattern.chars().collect::<Vec<_>>();
+    let mut in_class = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\\' => {
+                let Some(escaped) = next else {
+                    return unsupported("trailing backslash");
+                };
+                match escaped {
+                    '1'..='9' if !in_class => {
+                        return unsupported("backreferences need backtracking")
+                    }
+                    'g' | 'k' => return unsupported("backreferences need backtracking"),
+                    'G' | 'K' | 'X' | 'C' => {
+                        return unsupported(&format!("\\{escaped} has no RE2 equivalent"))
+                    }
+                    'h' => {
+                        out.push_str(if in_class { " \\t" } else { "[ \\t]" });
+                        change("\\h becomes [ \\t]");
+                    }
+                    'H' if !in_class => {
+                        out.push_str("[^ \\t]");
+                        change("\\H becomes [^ \\t]");
+                    }
+                    'R' if !in_class => {
+                        out.push_str("(?:\\r\\n|\\n|\\r)");
+                        change("\\R becomes (?:\\r\\n|\\n|\\r)");
+                    }
+                    'e' => {
+                        out.push_str("\\x1B");
+                        change("\\e becomes \\x1B");
+                    }
+                    'Z' => {
+                        out.push_str("\\z");
+                        change("\\Z becomes \\z");
+                    }
+                    'c' => return unsupported("control escapes are not supported"),
+                    escaped => {
+                        out.push(c);
+                        out.push(escaped);
+                    }
+                }
+                i += 2;
+                continue;
+            }
+            '[' if !in_class => {
+                in_class = true;
+                out.push(c);
+                // `[]abc]` and `[^]abc]` start with a literal `]`
+                if next == Some('^') {
+                    out.push('^');
+                    i += 1;
+                }
+                if chars.get(i + 1) == Some(&']') {
+                    out.push(']');
+                    i += 1;
+                }
+            }
+            '[' if in_class && next == Some(':') => {
+                // POSIX class such as `[:alpha:]`
+                let end = chars[i..]
+                    .windows(2)
+                    .position(|w| w == [':', ']'])
+                    .map(|p| i + p + 2)
+                    .unwrap_or(chars.len());
+                out.extend(&chars[i..end]);
+                i = end;
+                continue;
+            }
+            ']' if in_class => {
+                in_class = false;
+                out.push(c);
+            }
+            '(' if !in_class && next == Some('?') => {
+                let rest = chars[i + 2..].iter().collect::<String>();
+                if rest.starts_with('=')
+                    || rest.starts_with('!')
+                    || rest.starts_with("<=")
+                    || rest.starts_with("<!")
+                {
+                    return unsupported("lookarounds need backtracking");
+                }
+                if rest.starts_with('>') {
+                    return unsupported("atomic groups need backtracking");
+                }
+                if rest.starts_with('(') {
+                    return unsupported("conditional groups are not supported");
+                }
+                if rest.starts_with('R')
+                    || rest.starts_with('&')
+                    || rest.starts_with("P>")
+                    || rest.starts_with(|c: char| c.is_ascii_digit())
+                    || rest.starts_with(['+', '-'])
+                        && rest[1..].starts_with(|c: char| c.is_ascii_digit())
+                {
+                    return unsupported("recursion is not supported");
+                }
+                if rest.starts_with("P=") {
+                    return unsupported("backreferences need backtracking");
+                }
+                if rest.starts_with('#') {
+                    // `(?# comment )`
+                    let end = chars[i..]
+                        .iter()
+                        .position(|c| *c == ')')
+                        .map(|p| i + p + 1)
+                        .unwrap_or(chars.len());
+                    change("comments are removed");
+                    i = end;
+                    continue;
+                }
+                if rest.starts_with("P<") {
+                    out.push_str("(?");
+                    i += 2;
+                    continue;
+                }
+                if rest.starts_with('<') || rest.starts_with('\'') {
+                    // `(?<name>` and `(?'name'` become `(?P<name>`
+                    let close = if rest.starts_with('<') { '>' } else { '\'' };
+                    let name = rest[1..]
+                        .chars()
+                        .take_while(|c| *c != close)
+                        .collect::<String>();
+                    out.push_str(&format!("(?P<{name}>"));
+                    change("named groups use the (?P<name>) syntax");
+                    i += 3 + name.chars().count() + 1;
+                    continue;
+                }
+                if rest.starts_with('|') {
+                    return unsupported("branch reset groups are not supported");
+                }
+                // inline flags such as `(?i)` or `(?i:`; Go knows i, m, s and U
+                let flags = rest
+                    .chars()
+                    .take_while(|c| *c != ')' && *c != ':')
+                    .collect::<String>();
+                if let Some(flag) = flags.chars().find(|f| !"imsU-".contains(*f)) {
+                    return unsupported(&format!("the (?{flag}) flag is not supported"));
+                }
+                out.push_str("(?");
+                i += 2;
+                continue;
+            }
+            '*' | '+' | '?' | '}' if !in_class && next == Some('+') => {
+                // possessive quantifiers only differ by not backtracking
+                out.push(c);
+                change("possessive quantifiers become greedy ones");
+                i += 2;
+                continue;
+            }
+            '{' if !in_class => {
+                let end = chars[i..].iter().position(|c| *c == '}').map(|p| i + p);
+                let counts = end.map(|end| chars[i + 1..end].iter().collect::<String>());
+                if let Some(counts) = counts {
+                    let is_repeat = !counts.is_empty()
+                        && counts.chars().all(|c| c.is_ascii_digit() || c == ',');
+                    if is_repeat
+                        && counts
+                            .split(',')
+                            .filter_map(|n| n.parse::<u32>().ok())
+                            .any(|n| n > MAX_REPEAT)
+                    {
+                        return unsupported(&format!("repeat counts above {MAX_REPEAT}"));
+                    }
+                }
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+
+    if changes.is_empty() {
+        Compatibility::Compatible
+    } else {
+        Compatibility::Convertible {
+            pattern: out,
+            changes,
+        }
+    }
+}
+
+impl VirtualHost {
+    /// Checks every regex of the host that ends up in a Traefik rule or
+    /// middleware: rewrite patterns and conditions, regex sections,
+    /// `RedirectMatch` and `ProxyPassMatch`.
+    pub fn regex_checks(&self) -> Vec<RegexCheck> {
+        let mut checks = vec![];
+        let mut add = |source: String, pattern: &str| {
+            checks.push(RegexCheck {
+                source,
+                pattern: pattern.to_string(),
+                compatibility: check(pattern),
+            });
+        };
+
+        let location_rules = self.locations.iter().flat_map(|l| &l.rewrite_rules);
+        for rule in self.rewrite_rules.iter().chain(location_rules) {
+            for condition in rule.conditions.iter().filter(|c| c.is_regex()) {
+                add(
+                    format!("RewriteCond {}", condition.test_string),
+                    &condition.pattern,
+                );
+            }
+            add("RewriteRule".to_string(), &rule.pattern);
+        }
+        for location in self.locations.iter().filter(|l| l.regex) {
+            let source = match location.kind {
+                LocationKind::Location => "<LocationMatch>",
+                LocationKind::Directory => "<DirectoryMatch>",
+                LocationKind::Files => "<FilesMatch>",
+            };
+            add(source.to_string(), &location.path);
+        }
+        for redirect in self.redirects.iter().filter(|r| r.regex) {
+            add("RedirectMatch".to_string(), &redirect.path);
+        }
+        // rules declared inside a regex section were checked with it
+        for proxy_pass in self.proxy_passes.iter().filter(|p| p.regex) {
+            let from_location = self
+                .locations
+                .iter()
+                .any(|location| location.regex && location.path == proxy_pass.path);
+            if !from_location {
+                add("ProxyPassMatch".to_string(), &proxy_pass.path);
+            }
+        }
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn converted(pattern: &str) -> Compatibility {
+        let Compatibility::Convertible { pattern, changes } = check(pattern) else {
+            panic!("{pattern} isn't convertible: {:?}", check(pattern));
+        };
+        assert!(!changes.is_empty());
+        Compatibility::Convertible {
+            pattern,
+            changes: vec![],
+        }
+    }
+
+    #[test]
+    fn compatible_patterns() {
+        let cases = [
+            "^/api/(.*)$",
+            "^/(?P<version>v[0-9]+)/",
+            "(?i)^/admin",
+            "a{2,1000}",
+            "[]abc]+",
+            "[^]abc]+",
+            "[[:alpha:]]+[[:digit:]]{2}",
+            "[a++]",
+            "[(?=x)]",
+            "\\{1001\\}",
+        ];
+        for pattern in cases {
+            assert_eq!(check(pattern), Compatibility::Compatible, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn convertible_patterns() {
+        let cases = [
+            ("(?<id>[0-9]+)", "(?P<id>[0-9]+)"),
+            ("(?'id'[0-9]+)/(?<rest>.*)", "(?P<id>[0-9]+)/(?P<rest>.*)"),
+            ("a++b", "a+b"),
+            ("a*+b?+", "a*b?"),
+            ("x{2,3}+", "x{2,3}"),
+            ("^/a(?# comment)b", "^/ab"),
+            ("\\h+\\Z", "[ \\t]+\\z"),
+            ("[]\\h]", "[] \\t]"),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(
+                converted(pattern),
+                Compatibility::Convertible {
+                    pattern: expected.to_string(),
+                    changes: vec![],
+                },
+                "{pattern}"
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_patterns() {
+        let cases = [
+            ("^/(?=foo)", "lookarounds need backtracking"),
+            ("^/(?!foo)", "lookarounds need backtracking"),
+            ("(?<=a)b", "lookarounds need backtracking"),
+            ("(?<!a)b", "lookarounds need backtracking"),
+            ("[]abc](?=x)", "lookarounds need backtracking"),
+            ("[[:alpha:]](?!x)", "lookarounds need backtracking"),
+            ("(a)\\1", "backreferences need backtracking"),
+            ("(?<n>a)\\k<n>", "backreferences need backtracking"),
+            ("(?P=n)", "backreferences need backtracking"),
+            ("(?>a+)b", "atomic groups need backtracking"),
+            ("a{1001}", "repeat counts above 1000"),
+            ("a{1,1001}", "repeat counts above 1000"),
+            ("(?R)", "recursion is not supported"),
+            ("(?x)a b", "the (?x) flag is not supported"),
+        ];
+        for (pattern, reason) in cases {
+            assert_eq!(
+                check(pattern),
+                Compatibility::Unsupported {
+                    reason: reason.to_string()
+                },
+                "{pattern}"
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! mod_alias redirects: `Redirect`, `RedirectMatch`, `RedirectPermanent`
+//! and `RedirectTemp`.
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    go_replacement, re2, regex_escape,
+    rewrite::{capture_groups, whole},
+    traefik::{Middleware, RedirectRegex},
+};
+
+/// The nginx variables a `return` URL may use, with the group of the
+/// redirect regex standing in for them.
+pub(super) const NGINX_VARIABLES: [(&str, &str); 4] = [
+    ("$scheme", "${1}"),
+    ("$http_host", "${2}"),
+    ("$server_name", "${2}"),
+    ("$host", "${2}"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Redirect {
+    /// The URL-path prefix, or the regex for `RedirectMatch`.
+    pub(super) path: String,
+    pub(super) regex: bool,
+    pub(super) status: u16,
+    /// Where to send the client, absent for statuses such as `410 Gone`.
+    pub(super) target: Option<String>,
+}
+
+impl Redirect {
+    /// Parses `Redirect [status] URL-path URL` and its variants. Inside a
+    /// `<Location>` the URL-path is omitted and `location` is used instead.
+    pub(super) fn from_args(name: &str, args: &[String], location: Option<&str>) -> Option<Self> {
+        let mut args = args.iter().peekable();
+        let status = match name.to_lowercase().as_str() {
+            "redirectpermanent" => 301,
+            "redirecttemp" => 302,
+            "redirect" | "redirectmatch" => {
+                let status = args
+                    .peek()
+                    .and_then(|arg| match arg.to_lowercase().as_str() {
+                        "permanent" => Some(301),
+                        "temp" => Some(302),
+                        "seeother" => Some(303),
+                        "gone" => Some(410),
+                        code => code.parse::<u16>().ok(),
+                    });
+                if status.is_some() {
+                    args.next();
+                }
+                status.unwrap_or(302)
+            }
+            _ => return None,
+        };
+        // only 3xx statuses take a URL
+        let takes_url = (300..400).contains(&status);
+        let remaining = args.len();
+        let path = match location {
+            Some(location) if remaining < 1 + usize::from(takes_url) => location.to_string(),
+            _ => args.next()?.to_string(),
+        };
+        let target = if takes_url {
+            Some(args.next()?.to_string())
+        } else {
+            None
+        };
+        Some(Redirect {
+            path,
+            regex: name.eq_ignore_ascii_case("redirectmatch"),
+            status,
+            target,
+        })
+    }
+
+    /// Whether every request to the host is redirected, e.g.
+    /// `Redirect permanent / https://www.example.com/` or
+    /// `RedirectMatch ^/ https://www.example.com/`.
+    pub fn is_whole_host(&self) -> bool {
+        if self.regex {
+            self.path == "^/" || self.path == "^/.*"
+        } else {
+            self.path.trim_end_matches('/').is_empty()
+        }
+    }
+
+    /// A `redirectRegex` matching the full request URL. Like mod_alias the
+    /// rest of the path after a URL-path prefix is appended to the target,
+    /// and the query string is kept unless the target sets its own.
+    pub(super) fn to_middleware(&self) -> Result<Middleware, String> {
+        let Some(target) = &self.target else {
+            return Err(format!("traefik can't answer with status {}", self.status));
+        };
+        let target = &NGINX_VARIABLES
+            .iter()
+            .fold(target.clone(), |target, (variable, group)| {
+                target.replace(variable, group)
+            });
+        let permanent = match self.status {
+            301 | 308 => true,
+            302 | 307 => false,
+            303 => {
+                debug!("{} is sent as a temporary redirect", self.path);
+                false
+            }
+            status => return Err(format!("traefik can't redirect with status {status}")),
+        };
+
+        // a target with its own query string replaces the request's
+        let own_query = target.contains('?');
+        let (regex, mut replacement) = if self.regex {
+            let pattern = re2::to_re2(&self.path)?;
+            let path = whole(&pattern, "[^?]", false);
+            let query_group = 3 + capture_groups(&path) + 1;
+            let mut replacement = go_replacement(target, 3);
+            if !own_query {
+                replacement.push_str(&format!("${{{query_group}}}"));
+            }
+            (format!("^(https?)://([^/]+)({path})(\\?.*)?$"), replacement)
+        } else {
+            let path = self.path.trim_end_matches('/');
+            let replacement = if own_query {
+                target.to_string()
+            } else {
+                format!("{}${{3}}${{4}}", target.trim_end_matches('/'))
+            };
+            (
+                format!(
+                    "^(https?)://([^/]+){}(/[^?]*)?(\\?.*)?$",
+                    regex_escape(path)
+                ),
+                replacement,
+            )
+        };
+        if replacement.starts_with('/') {
+            replacement = format!("${{1}}://${{2}}{replacement}");
+        }
+
+        Ok(Middleware {
+            redirect_regex: Some(RedirectRegex {
+                regex,
+                replacement,
+                permanent,
+            }),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,282 @@
+//! Shared middlewares declared in a config file and the rules attaching
+//! them to hosts.
+//!
+//! ```toml
+//! [middlewares.https-only.redirectScheme]
+//! scheme = "https"
+//! permanent = true
+//!
+//! [middlewares.secured.chain]
+//! middlewares = ["https-only"]
+//!
+//! [[attach]]
+//! middlewares = ["secured"]
+//! tls = true
+//! host = "*.example.com"
+//! file = "sites-enabled/*.conf"
+//! ```
+
+use std::{collections::BTreeMap, path::Path};
+
+use glob::Pattern;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{traefik, traefik::Middleware, VirtualHost};
+use crate::error::{ParserError, ParserResult};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MiddlewareRegistry {
+    #[serde(default)]
+    pub middlewares: BTreeMap<String, Middleware>,
+    #[serde(default)]
+    pub attach: Vec<AttachRule>,
+}
+
+/// Attaches middlewares to the hosts matching every criterion given; a rule
+/// without any applies to all hosts.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct AttachRule {
+    pub middlewares: Vec<String>,
+    /// Only hosts with (or without) `SSLEngine on`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+    #[serde(flatten)]
+    pub matcher: HostMatcher,
+}
+
+/// Selects hosts by name or by the file they were read from; both have to
+/// match when given.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct HostMatcher {
+    /// A glob matched against the server name and aliases.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// A glob matched against the file the host was read from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+/// What every host got before registries existed.
+impl Default for MiddlewareRegistry {
+    fn default() -> Self {
+        Self {
+            middlewares: traefik::default_middlewares(),
+            attach: vec![AttachRule {
+                middlewares: vec!["https-only".to_string()],
+                ..Default::default()
+            }],
+        }
+    }
+}
+
+impl MiddlewareRegistry {
+    /// Reads a registry from a `.toml`, `.yaml` or `.yml` file.
+    pub fn load(path: &Path) -> ParserResult<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let invalid = |err: String| ParserError::Config(format!("{}: {err}", path.display()));
+        let registry: Self = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&data).map_err(|e| invalid(e.to_string()))?
+            }
+            _ => toml::from_str(&data).map_err(|e| invalid(e.to_string()))?,
+        };
+        for rule in &registry.attach {
+            rule.matcher.validate().map_err(invalid)?;
+            // `name@provider` refers to a middleware defined elsewhere
+            for name in rule.middlewares.iter().filter(|name| !name.contains('@')) {
+                if !registry.middlewares.contains_key(name) {
+                    warn!(
+                        "{}: middleware {name} is attached but not defined",
+                        path.display()
+                    );
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    /// The middlewares attached to the host, in the order of the rules.
+    pub fn middlewares_for(&self, virtual_host: &VirtualHost) -> Vec<String> {
+        let mut middlewares: Vec<String> = vec![];
+        for rule in self.attach.iter().filter(|rule| rule.matches(virtual_host)) {
+            for name in &rule.middlewares {
+                if !middlewares.contains(name) {
+                    middlewares.push(name.clone());
+                }
+            }
+        }
+        middlewares
+    }
+}
+
+impl AttachRule {
+    fn matches(&self, virtual_host: &VirtualHost) -> bool {
+        let tls_matches = self
+            .tls
+            .is_none_or(|tls| tls == virtual_host.ssl_config.enabled);
+        tls_matches && self.matcher.matches(virtual_host)
+    }
+}
+
+impl HostMatcher {
+    /// Checks that the globs compile.
+    pub(super) fn validate(&self) -> Result<(), String> {
+        for pattern in self.host.iter().chain(&self.file) {
+            Pattern::new(pattern).map_err(|e| format!("{pattern}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn matches(&self, virtual_host: &VirtualHost) -> bool {
+        if let Some(Ok(pattern)) = self.host.as_deref().map(Pattern::new) {
+            let mut names =
+                std::iter::once(&virtual_host.server_name).chain(&virtual_host.server_aliases);
+            if !names.any(|name| pattern.matches(name)) {
+                return false;
+            }
+        }
+        if let Some(file) = &self.file {
+            let Some(source) = &virtual_host.source else {
+                return false;
+            };
+            // relative globs match the end of the path
+            let matched = [file.clone(), format!("*/{file}")]
+                .iter()
+                .filter_map(|pattern| Pattern::new(pattern).ok())
+                .any(|pattern| pattern.matches_path(source));
+            if !matched {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::cmd::configs::SslConfig;
+
+    fn host(name: &str, aliases: &[&str], source: Option<&str>, tls: bool) -> VirtualHost {
+        let mut builder = VirtualHost::builder()
+            .server_name(name.to_string())
+            .server_aliases(aliases.iter().map(|alias| alias.to_string()).collect())
+            .ssl_config(SslConfig {
+                enabled: tls,
+                ..Default::default()
+            });
+        if let Some(source) = source {
+            builder = builder.source(PathBuf::from(source));
+        }
+        builder.build()
+    }
+
+    fn matcher(host: Option<&str>, file: Option<&str>) -> HostMatcher {
+        HostMatcher {
+            host: host.map(str::to_string),
+            file: file.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn host_globs_match_names_and_aliases() {
+        let api = host("api.example.com", &["www.example.org"], None, false);
+        let cases = [
+            (Some("*.example.com"), true),
+            (Some("api.example.com"), true),
+            (Some("*.example.org"), true),
+            (Some("*.example.net"), false),
+            (Some("example.com"), false),
+            (None, true),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(
+                matcher(pattern, None).matches(&api),
+                expected,
+                "{pattern:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn file_globs_match_the_end_of_the_source() {
+        let site = host(
+            "example.com",
+            &[],
+            Some("/etc/apache2/sites-enabled/example.conf"),
+            false,
+        );
+        let cases = [
+            ("sites-enabled/*.conf", true),
+            ("/etc/apache2/sites-enabled/*.conf", true),
+            ("*.conf", true),
+            ("example.conf", true),
+            ("sites-available/*.conf", false),
+            ("/sites-enabled/*.conf", false),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(
+                matcher(None, Some(pattern)).matches(&site),
+                expected,
+                "{pattern}"
+            );
+        }
+
+        // hosts without a source never match a file glob
+        let unknown = host("example.com", &[], None, false);
+        assert!(!matcher(None, Some("*.conf")).matches(&unknown));
+    }
+
+    #[test]
+    fn host_and_file_both_have_to_match() {
+        let site = host("api.example.com", &[], Some("/etc/sites/api.conf"), false);
+        assert!(matcher(Some("*.example.com"), Some("sites/*.conf")).matches(&site));
+        assert!(!matcher(Some("*.example.org"), Some("sites/*.conf")).matches(&site));
+        assert!(!matcher(Some("*.example.com"), Some("other/*.conf")).matches(&site));
+    }
+
+    #[test]
+    fn invalid_globs_are_rejected() {
+        assert!(matcher(Some("[example"), None).validate().is_err());
+        assert!(matcher(None, Some("sites/[*.conf")).validate().is_err());
+        assert!(matcher(Some("*.example.com"), Some("*.conf"))
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn rules_attach_in_order_without_duplicates() {
+        let registry = MiddlewareRegistry {
+            middlewares: BTreeMap::new(),
+            attach: vec![
+                AttachRule {
+                    middlewares: vec!["https-only".to_string()],
+                    ..Default::default()
+                },
+                AttachRule {
+                    middlewares: vec!["secured".to_string(), "https-only".to_string()],
+                    tls: Some(true),
+                    matcher: matcher(Some("*.example.com"), None),
+                },
+                AttachRule {
+                    middlewares: vec!["legacy".to_string()],
+                    matcher: matcher(None, Some("legacy/*.conf")),
+                    ..Default::default()
+                },
+            ],
+        };
+        let secure = host("api.example.com", &[], Some("/etc/sites/api.conf"), true);
+        let plain = host("api.example.com", &[], Some("/etc/legacy/api.conf"), false);
+        assert_eq!(
+            registry.middlewares_for(&secure),
+            vec!["https-only", "secured"]
+        );
+        assert_eq!(
+            registry.middlewares_for(&plain),
+            vec!["https-only", "legacy"]
+        );
+    }
+}
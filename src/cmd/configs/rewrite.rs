@@ -0,0 +1,829 @@
+//! Structured model of mod_rewrite rules, so emitters can tell redirects,
+//! internal rewrites and proxy rules apart.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    re2,
+    traefik::{Middleware, RedirectRegex, ReplacePathRegex},
+    Upstream,
+};
+
+/// A `RewriteRule` along with the `RewriteCond` lines gating it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RewriteRule {
+    pub(super) pattern: String,
+    /// `RewriteRule !pattern` matches when the pattern doesn't.
+    pub(super) negated: bool,
+    /// The replacement, `-` leaving the URL untouched.
+    pub(super) substitution: String,
+    pub(super) conditions: Vec<RewriteCond>,
+    pub(super) flags: RewriteFlags,
+}
+
+impl RewriteRule {
+    /// Builds a rule from `Pattern Substitution [flags]`, attaching the
+    /// conditions read since the previous rule.
+    pub(super) fn from_args(args: &[String], conditions: Vec<RewriteCond>) -> Option<Self> {
+        let (pattern, negated) = split_negation(args.first()?);
+        Some(RewriteRule {
+            pattern,
+            negated,
+            substitution: args.get(1).cloned().unwrap_or_else(|| "-".to_string()),
+            conditions,
+            flags: args
+                .get(2)
+                .map(|flags| RewriteFlags::parse(flags))
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Whether the substitution points at another host.
+    pub fn is_absolute(&self) -> bool {
+        let substitution = self.substitution.to_lowercase();
+        substitution.starts_with("http://") || substitution.starts_with("https://")
+    }
+
+    /// Whether the client is sent elsewhere rather than the request being
+    /// rewritten in place. Absolute substitutions redirect even without
+    /// `[R]` unless they are proxied.
+    pub fn is_redirect(&self) -> bool {
+        self.flags.redirect.is_some() || (self.is_absolute() && !self.flags.proxy)
+    }
+}
+
+impl Display for RewriteRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let negation = if self.negated { "!" } else { "" };
+        write!(
+            f,
+            "RewriteRule {negation}{} {}",
+            self.pattern, self.substitution
+        )
+    }
+}
+
+/// A `RewriteCond TestString CondPattern [flags]` line.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RewriteCond {
+    pub(super) test_string: String,
+    pub(super) pattern: String,
+    pub(super) negated: bool,
+    /// `[NC]`
+    pub(super) nocase: bool,
+    /// `[OR]`, combining with the next condition instead of and-ing.
+    pub(super) or_next: bool,
+    /// `[NV]`
+    pub(super) novary: bool,
+}
+
+impl RewriteCond {
+    pub(super) fn from_args(args: &[String]) -> Option<Self> {
+        let (pattern, negated) = split_negation(args.get(1)?);
+        let mut condition = RewriteCond {
+            test_string: args[0].to_string(),
+            pattern,
+            negated,
+            ..Default::default()
+        };
+        for flag in args
+            .get(2)
+            .map(|flags| flag_list(flags))
+            .unwrap_or_default()
+        {
+            match flag.to_lowercase().as_str() {
+                "nc" | "nocase" => condition.nocase = true,
+                "or" | "ornext" => condition.or_next = true,
+                "nv" | "novary" => condition.novary = true,
+                _ => {}
+            }
+        }
+        Some(condition)
+    }
+
+    /// Whether the pattern is a regular expression rather than a file test
+    /// (`-f`, `-d`, ...) or a comparison (`=value`, `<value`, `-eq 1`, ...).
+    pub fn is_regex(&self) -> bool {
+        !(self.pattern.starts_with('-') && self.pattern.len() > 1
+            || self.pattern.starts_with(['=', '<', '>']))
+    }
+}
+
+/// The `[...]` flags of a `RewriteRule`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct RewriteFlags {
+    /// `[L]`
+    pub(super) last: bool,
+    /// `[END]`
+    pub(super) end: bool,
+    /// `[NC]`
+    pub(super) nocase: bool,
+    /// `[P]`
+    pub(super) proxy: bool,
+    /// `[PT]`
+    pub(super) passthrough: bool,
+    /// `[QSA]`
+    pub(super) qsappend: bool,
+    /// `[QSD]`
+    pub(super) qsdiscard: bool,
+    /// `[NE]`
+    pub(super) noescape: bool,
+    /// `[F]`
+    pub(super) forbidden: bool,
+    /// `[G]`
+    pub(super) gone: bool,
+    /// `[C]`
+    pub(super) chain: bool,
+    /// `[N]`
+    pub(super) next: bool,
+    /// `[R]` or `[R=code]`, `R` alone being a 302.
+    pub(super) redirect: Option<u16>,
+    /// `[S=n]`
+    pub(super) skip: Option<u32>,
+    /// `[E=VAR:value]`
+    pub(super) env: Vec<String>,
+    /// `[CO=name:value:domain...]`
+    pub(super) cookies: Vec<String>,
+    /// `[T=mime-type]`
+    pub(super) mime_type: Option<String>,
+    /// `[H=handler]`
+    pub(super) handler: Option<String>,
+    /// Flags that aren't modelled, kept as written.
+    pub(super) other: Vec<String>,
+}
+
+impl RewriteFlags {
+    /// Parses a flag list such as `[L,R=301,NC]`. Flag names are case
+    /// insensitive and may be given in their long form.
+    pub fn parse(flags: &str) -> Self {
+        let mut parsed = RewriteFlags::default();
+        for flag in flag_list(flags) {
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_lowercase(), Some(value.to_string())),
+                None => (flag.to_lowercase(), None),
+            };
+            match name.as_str() {
+                "l" | "last" => parsed.last = true,
+                "end" => parsed.end = true,
+                "nc" | "nocase" => parsed.nocase = true,
+                "p" | "proxy" => parsed.proxy = true,
+                "pt" | "passthrough" => parsed.passthrough = true,
+                "qsa" | "qsappend" => parsed.qsappend = true,
+                "qsd" | "qsdiscard" => parsed.qsdiscard = true,
+                "ne" | "noescape" => parsed.noescape = true,
+                "f" | "forbidden" => parsed.forbidden = true,
+                "g" | "gone" => parsed.gone = true,
+                "c" | "chain" => parsed.chain = true,
+                "n" | "next" => parsed.next = true,
+                "r" | "redirect" => {
+                    parsed.redirect = Some(match value.as_deref().map(str::to_lowercase) {
+                        None => 302,
+                        Some(code) => match code.as_str() {
+                            "permanent" => 301,
+                            "temp" => 302,
+                            "seeother" => 303,
+                            code => code.parse().unwrap_or(302),
+                        },
+                    })
+                }
+                "s" | "skip" => parsed.skip = value.and_then(|n| n.parse().ok()),
+                "e" | "env" => parsed.env.extend(value),
+                "co" | "cookie" => parsed.cookies.extend(value),
+                "t" | "type" => parsed.mime_type = value,
+                "h" | "handler" => parsed.handler = value,
+                _ => parsed.other.push(flag),
+            }
+        }
+        parsed
+    }
+
+    /// Whether no later rule is applied after this one matches.
+    pub fn stops(&self) -> bool {
+        self.last || self.end || self.redirect.is_some() || self.forbidden || self.gone
+    }
+}
+
+/// A `RewriteMap MapName MapType:MapSource` declaration.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RewriteMap {
+    pub(super) name: String,
+    /// `txt`, `rnd`, `dbm`, `int`, `prg` or `dbd`.
+    pub(super) map_type: String,
+    pub(super) source: String,
+}
+
+impl RewriteMap {
+    pub(super) fn from_args(args: &[String]) -> Option<Self> {
+        let (map_type, source) = args.get(1)?.split_once(':')?;
+        Some(RewriteMap {
+            name: args[0].to_string(),
+            map_type: map_type.to_lowercase(),
+            source: source.to_string(),
+        })
+    }
+}
+
+/// What a rewrite rule turns into in Traefik.
+pub(super) enum Translation {
+    /// A middleware added to every router of the host.
+    Middleware(Middleware),
+    /// A router of its own, for `[P]` rules proxying matching paths.
+    Proxy {
+        path_regex: String,
+        upstream: Upstream,
+        middleware: Middleware,
+    },
+}
+
+impl RewriteRule {
+    /// Translates the rule, or explains why Traefik can't express it.
+    ///
+    /// Traefik runs every middleware of a router, so `[L]` can't stop later
+    /// rules and rewritten paths aren't routed again the way Apache maps
+    /// them onto `ProxyPass` after rewriting.
+    pub(super) fn to_traefik(&self) -> Result<Translation, String> {
+        let flags = &self.flags;
+        if self.negated {
+            return Err("negated patterns have no RE2 equivalent".to_string());
+        }
+        if flags.chain || flags.next || flags.skip.is_some() {
+            return Err("[C], [N] and [S] control flow is not supported".to_string());
+        }
+        if flags.forbidden || flags.gone {
+            return Err("[F] and [G] responses are not supported".to_string());
+        }
+        if self.substitution == "-" {
+            return Err("the rule doesn't change the URL".to_string());
+        }
+        let rule = self.to_re2()?;
+        if flags.proxy {
+            rule.to_proxy()
+        } else if rule.is_redirect() {
+            rule.to_redirect().map(Translation::Middleware)
+        } else {
+            rule.to_rewrite().map(Translation::Middleware)
+        }
+    }
+
+    /// A copy of the rule with its regexes converted for RE2.
+    fn to_re2(&self) -> Result<RewriteRule, String> {
+        let mut rule = self.clone();
+        rule.pattern = re2::to_re2(&self.pattern)?;
+        for condition in rule.conditions.iter_mut().filter(|c| c.is_regex()) {
+            condition.pattern = re2::to_re2(&condition.pattern)?;
+        }
+        Ok(rule)
+    }
+
+    /// A `redirectRegex` matching the full request URL. Host and scheme
+    /// conditions are folded into the regex so `%N` can refer to them, the
+    /// groups being the scheme, the host, the host condition's groups, the
+    /// path, the rule's groups and finally the query string.
+    fn to_redirect(&self) -> Result<Middleware, String> {
+        let mut scheme = "https?".to_string();
+        let mut host = "[^/]+".to_string();
+        let mut host_groups = None;
+        for condition in &self.conditions {
+            if condition.or_next {
+                return Err("[OR] conditions are not supported".to_string());
+            }
+            match condition.test_string.as_str() {
+                "%{HTTPS}" | "%{REQUEST_SCHEME}" => {
+                    let value = condition
+                        .pattern
+                        .trim_start_matches(['^', '='])
+                        .trim_end_matches('$')
+                        .to_lowercase();
+                    let https = match value.as_str() {
+                        "on" | "https" => true,
+                        "off" | "http" => false,
+                        _ => return Err(format!("unsupported {} test", condition.test_string)),
+                    };
+                    scheme = if https != condition.negated {
+                        "https"
+                    } else {
+                        "http"
+                    }
+                    .to_string();
+                }
+                "%{HTTP_HOST}" | "%{SERVER_NAME}" => {
+                    if condition.negated || !condition.is_regex() || host_groups.is_some() {
+                        return Err("only a single positive host regex is supported".to_string());
+                    }
+                    host = whole(&condition.pattern, "[^/]", condition.nocase);
+                    host_groups = Some(capture_groups(&host));
+                }
+                other => return Err(format!("conditions on {other} are not supported")),
+            }
+        }
+        let path = whole(&self.pattern, "[^?]", self.flags.nocase);
+        let regex = format!("^({scheme})://({host})({path})(\\?(.*))?$");
+
+        let cond_offset = 2;
+        let path_group = 3 + host_groups.unwrap_or_default();
+        let query_group = path_group + capture_groups(&path) + 1;
+        let mut replacement = go_backrefs(&self.substitution, |reference| match reference {
+            Reference::Rule(n) => Some(path_group + n),
+            Reference::Condition(n) => host_groups.map(|_| cond_offset + n),
+            Reference::Variable(name) => match name {
+                "REQUEST_SCHEME" => Some(1),
+                "HTTP_HOST" | "SERVER_NAME" => Some(2),
+                "REQUEST_URI" => Some(path_group),
+                "QUERY_STRING" => Some(query_group + 1),
+                _ => None,
+            },
+        })?;
+        if replacement.starts_with('/') {
+            replacement = format!("${{1}}://${{2}}{replacement}");
+        } else if !self.is_absolute() {
+            return Err("relative substitutions are not supported".to_string());
+        }
+
+        // like Apache, keep the query string unless the substitution sets
+        // its own or ends in `?`
+        if let Some(stripped) = replacement.strip_suffix('?') {
+            replacement = stripped.to_string();
+        } else if replacement.contains('?') {
+            if self.flags.qsappend {
+                replacement.push_str(&format!("&${{{}}}", query_group + 1));
+            }
+        } else if !self.flags.qsdiscard {
+            replacement.push_str(&format!("${{{query_group}}}"));
+        }
+
+        Ok(Middleware {
+            redirect_regex: Some(RedirectRegex {
+                regex,
+                replacement,
+                permanent: matches!(self.flags.redirect, Some(301 | 308)),
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// A `replacePathRegex` rewriting the path in place.
+    fn to_rewrite(&self) -> Result<Middleware, String> {
+        if !self.conditions.is_empty() {
+            return Err("conditions on internal rewrites are not supported".to_string());
+        }
+        if self.substitution.contains('?') {
+            return Err("traefik can't rewrite the query string".to_string());
+        }
+        if !self.substitution.starts_with('/') {
+            return Err("relative substitutions are not supported".to_string());
+        }
+        let regex = format!("^{}$", whole(&self.pattern, ".", self.flags.nocase));
+        let replacement = path_backrefs(&self.substitution)?;
+        Ok(Middleware {
+            replace_path_regex: Some(ReplacePathRegex { regex, replacement }),
+            ..Default::default()
+        })
+    }
+
+    /// A router sending the matching paths to the upstream of the
+    /// substitution, with the path rewritten on the way.
+    fn to_proxy(&self) -> Result<Translation, String> {
+        if !self.conditions.is_empty() {
+            return Err("conditions on [P] rules are not supported".to_string());
+        }
+        let upstream = Upstream::parse(&self.substitution)
+            .ok_or_else(|| "[P] needs an absolute URL".to_string())?;
+        if upstream.host.contains(['$', '%']) || upstream.port.contains(['$', '%']) {
+            return Err("the backend depends on the request".to_string());
+        }
+        if upstream.path.contains('?') {
+            return Err("traefik can't rewrite the query string".to_string());
+        }
+        let path_regex = format!("^{}$", whole(&self.pattern, ".", self.flags.nocase));
+        let replacement = if upstream.path.is_empty() {
+            "/".to_string()
+        } else {
+            path_backrefs(&upstream.path)?
+        };
+        Ok(Translation::Proxy {
+            middleware: Middleware {
+                replace_path_regex: Some(ReplacePathRegex {
+                    regex: path_regex.clone(),
+                    replacement,
+                }),
+                ..Default::default()
+            },
+            path_regex,
+            upstream,
+        })
+    }
+}
+
+/// A backreference or server variable in a substitution.
+enum Reference<'a> {
+    /// `$N`
+    Rule(usize),
+    /// `%N`
+    Condition(usize),
+    /// `%{NAME}`
+    Variable(&'a str),
+}
+
+/// Converts the backreferences of a path substitution, which only has the
+/// rule's own groups.
+fn path_backrefs(substitution: &str) -> Result<String, String> {
+    go_backrefs(substitution, |reference| match reference {
+        Reference::Rule(n) => Some(n),
+        _ => None,
+    })
+}
+
+/// Rewrites `$N`, `%N` and `%{VAR}` into Go's `${N}` using `group` to find
+/// the matching group. Literal `$` is escaped as `$$`.
+fn go_backrefs(
+    substitution: &str,
+    group: impl Fn(Reference) -> Option<usize>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = substitution.chars().peekable();
+    while let Some(c) = chars.next() {
+        let reference = match c {
+            '\\' if matches!(chars.peek(), Some('$' | '%')) => {
+                let escaped = chars.next().unwrap_or_default();
+                out.push_str(if escaped == '$' { "$$" } else { "%" });
+                continue;
+            }
+            '$' | '%' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let n = chars
+                    .next()
+                    .and_then(|d| d.to_digit(10))
+                    .unwrap_or_default() as usize;
+                if c == '$' {
+                    Reference::Rule(n)
+                } else {
+                    Reference::Condition(n)
+                }
+            }
+            '%' if chars.peek() == Some(&'{') => {
+                let name = chars
+                    .by_ref()
+                    .skip(1)
+                    .take_while(|c| *c != '}')
+                    .collect::<String>();
+                match group(Reference::Variable(&name)) {
+                    Some(n) => out.push_str(&format!("${{{n}}}")),
+                    None => return Err(format!("%{{{name}}} is not supported")),
+                }
+                continue;
+            }
+            '$' => {
+                out.push_str("$$");
+                continue;
+            }
+            c => {
+                out.push(c);
+                continue;
+            }
+        };
+        let text = match reference {
+            Reference::Rule(n) => format!("${n}"),
+            Reference::Condition(n) => format!("%{n}"),
+            Reference::Variable(_) => unreachable!(),
+        };
+        match group(reference) {
+            Some(n) => out.push_str(&format!("${{{n}}}")),
+            None => return Err(format!("{text} can't be mapped onto the traefik regex")),
+        }
+    }
+    Ok(out)
+}
+
+/// Turns a pattern into a fragment matching the whole value, as
+/// mod_rewrite replaces the whole URL while Go only replaces the match.
+/// `any` stands in for `.` so the fragment can't run into the next part of
+/// the URL.
+pub(super) fn whole(pattern: &str, any: &str, nocase: bool) -> String {
+    let (start, core) = match pattern.strip_prefix('^') {
+        Some(core) => (String::new(), core),
+        None => (format!("{any}*?"), pattern),
+    };
+    let (core, end) = match core.strip_suffix('$') {
+        Some(stripped) if !stripped.ends_with('\\') => (stripped, String::new()),
+        _ => (core, format!("{any}*")),
+    };
+    let core = if any == "." {
+        core.to_string()
+    } else {
+        confine(core, any)
+    };
+    let core = if nocase {
+        format!("(?i:{core})")
+    } else if core.contains('|') {
+        format!("(?:{core})")
+    } else {
+        core
+    };
+    format!("{start}{core}{end}")
+}
+
+/// Replaces `.` outside of character classes with `any`.
+fn confine(pattern: &str, any: &str) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                out.extend(chars.next());
+            }
+            '[' => {
+                in_class = true;
+                out.push(c);
+            }
+            ']' => {
+                in_class = false;
+                out.push(c);
+            }
+            '.' if !in_class => out.push_str(any),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Counts the capturing groups of a regex.
+pub(super) fn capture_groups(pattern: &str) -> usize {
+    let mut count = 0;
+    let mut chars = pattern.chars().peekable();
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '(' if !in_class => {
+                if chars.peek() != Some(&'?') {
+                    count += 1;
+                } else {
+                    // named groups capture as well
+                    let rest = chars.clone().skip(1).take(2).collect::<String>();
+                    if rest.starts_with('<') && rest != "<=" && rest != "<!"
+                        || rest.starts_with("P<")
+                    {
+                        count += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+fn split_negation(pattern: &str) -> (String, bool) {
+    match pattern.strip_prefix('!') {
+        Some(pattern) => (pattern.to_string(), true),
+        None => (pattern.to_string(), false),
+    }
+}
+
+/// Splits `[A,B=c]` into its flags.
+fn flag_list(flags: &str) -> Vec<String> {
+    flags
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn rule(conditions: &[&str], line: &str) -> RewriteRule {
+        let conditions = conditions
+            .iter()
+            .map(|condition| RewriteCond::from_args(&args(condition)).unwrap())
+            .collect();
+        RewriteRule::from_args(&args(line), conditions).unwrap()
+    }
+
+    #[test]
+    fn flags() {
+        let cases = [
+            (
+                "[L,R=301,NC]",
+                RewriteFlags {
+                    last: true,
+                    nocase: true,
+                    redirect: Some(301),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[last,redirect,nocase,qsappend]",
+                RewriteFlags {
+                    last: true,
+                    nocase: true,
+                    redirect: Some(302),
+                    qsappend: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "[R=permanent]",
+                RewriteFlags {
+                    redirect: Some(301),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[redirect=seeother]",
+                RewriteFlags {
+                    redirect: Some(303),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[skip=x]",
+                RewriteFlags {
+                    skip: None,
+                    ..Default::default()
+                },
+            ),
+            (
+                "[S=2]",
+                RewriteFlags {
+                    skip: Some(2),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[E=NOCACHE:1,env=MODE:dev,T=text/plain]",
+                RewriteFlags {
+                    env: vec!["NOCACHE:1".into(), "MODE:dev".into()],
+                    mime_type: Some("text/plain".into()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[PT,BNP,DPI,L]",
+                RewriteFlags {
+                    passthrough: true,
+                    last: true,
+                    other: vec!["BNP".into(), "DPI".into()],
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (flags, expected) in cases {
+            assert_eq!(RewriteFlags::parse(flags), expected, "{flags}");
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let cases = [
+            ("%{HTTPS} off", "off", false, false, false),
+            ("%{HTTP_HOST} !^www\\. [NC,OR]", "^www\\.", true, true, true),
+            ("%{HTTP_HOST} a [nocase,ornext]", "a", false, true, true),
+            ("%{REQUEST_FILENAME} !-f [NV]", "-f", true, false, false),
+        ];
+        for (line, pattern, negated, nocase, or_next) in cases {
+            let condition = RewriteCond::from_args(&args(line)).unwrap();
+            assert_eq!(
+                (
+                    condition.pattern.as_str(),
+                    condition.negated,
+                    condition.nocase,
+                    condition.or_next
+                ),
+                (pattern, negated, nocase, or_next),
+                "{line}"
+            );
+        }
+        assert!(RewriteCond::from_args(&args("%{HTTPS}")).is_none());
+        assert!(!RewriteCond::from_args(&args("%{REQUEST_FILENAME} -d"))
+            .unwrap()
+            .is_regex());
+    }
+
+    #[test]
+    fn maps() {
+        let map = RewriteMap::from_args(&args("users TXT:/etc/httpd/users.txt")).unwrap();
+        assert_eq!(
+            (
+                map.name.as_str(),
+                map.map_type.as_str(),
+                map.source.as_str()
+            ),
+            ("users", "txt", "/etc/httpd/users.txt")
+        );
+        assert!(RewriteMap::from_args(&args("users /etc/httpd/users.txt")).is_none());
+        assert!(RewriteMap::from_args(&args("users")).is_none());
+    }
+
+    fn redirect(conditions: &[&str], line: &str) -> (String, String, bool) {
+        match rule(conditions, line).to_traefik() {
+            Ok(Translation::Middleware(Middleware {
+                redirect_regex: Some(redirect),
+                ..
+            })) => (redirect.regex, redirect.replacement, redirect.permanent),
+            Ok(_) => panic!("{line} isn't a redirect"),
+            Err(reason) => panic!("{line}: {reason}"),
+        }
+    }
+
+    #[test]
+    fn redirect_groups() {
+        let cases: [(&[&str], &str, &str, &str); 5] = [
+            // the scheme test narrows the scheme group, $1 follows the path
+            (
+                &["%{HTTPS} off"],
+                "^(.*)$ https://%{HTTP_HOST}$1 [R=301,L]",
+                r"^(http)://([^/]+)(([^?]*))(\?(.*))?$",
+                "https://${2}${4}${5}",
+            ),
+            // %N points into the host, pushing the path and query groups back
+            (
+                &[r"%{HTTP_HOST} ^(www\.)?(.+)$ [NC]"],
+                "^/(.*)$ https://%2/$1 [R=301]",
+                r"^(https?)://((?i:(www\.)?([^/]+)))(/([^?]*))(\?(.*))?$",
+                "https://${4}/${6}${7}",
+            ),
+            // [QSA] appends the request's query to the substitution's
+            (
+                &[],
+                "^/old/(.*)$ /new/$1?ref=old [R,QSA]",
+                r"^(https?)://([^/]+)(/old/([^?]*))(\?(.*))?$",
+                "${1}://${2}/new/${4}?ref=old&${6}",
+            ),
+            // a trailing `?` drops the query
+            (
+                &[],
+                "^/old/(.*)$ /new/$1? [R=301]",
+                r"^(https?)://([^/]+)(/old/([^?]*))(\?(.*))?$",
+                "${1}://${2}/new/${4}",
+            ),
+            (
+                &[],
+                "^/old/(.*)$ /new/$1 [R,QSD]",
+                r"^(https?)://([^/]+)(/old/([^?]*))(\?(.*))?$",
+                "${1}://${2}/new/${4}",
+            ),
+        ];
+        for (conditions, line, regex, replacement) in cases {
+            let (actual_regex, actual_replacement, _) = redirect(conditions, line);
+            assert_eq!(actual_regex, regex, "{line}");
+            assert_eq!(actual_replacement, replacement, "{line}");
+        }
+    }
+
+    #[test]
+    fn redirects_keep_the_query_by_default() {
+        let (_, replacement, permanent) = redirect(&[], "^/a/(.*)$ /b/$1 [R]");
+        assert_eq!(replacement, "${1}://${2}/b/${4}${5}");
+        assert!(!permanent);
+    }
+
+    #[test]
+    fn proxy_rules_get_a_router() {
+        let Ok(Translation::Proxy {
+            path_regex,
+            upstream,
+            middleware,
+        }) = rule(&[], "^/api/(.*)$ http://backend:8080/v2/$1 [P]").to_traefik()
+        else {
+            panic!("[P] isn't routed");
+        };
+        assert_eq!(path_regex, "^/api/(.*)$");
+        assert_eq!(upstream.base_url(), "http://backend:8080");
+        let replace = middleware.replace_path_regex.unwrap();
+        assert_eq!(
+            (replace.regex.as_str(), replace.replacement.as_str()),
+            ("^/api/(.*)$", "/v2/${1}")
+        );
+    }
+
+    #[test]
+    fn untranslatable_rules() {
+        let cases: [(&[&str], &str); 6] = [
+            (&["%{HTTP_HOST} a [OR]"], "^/ /b [R]"),
+            (&["%{REMOTE_ADDR} ^10\\."], "^/ /b [R]"),
+            (&[], "^/(.*)$ http://$1:8080/ [P]"),
+            (&[], "^/old /new?x=1 [PT]"),
+            (&[], "!^/old /new [R]"),
+            (&[], "^/a - [F]"),
+        ];
+        for (conditions, line) in cases {
+            assert!(rule(conditions, line).to_traefik().is_err(), "{line}");
+        }
+    }
+}
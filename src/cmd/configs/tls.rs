@@ -0,0 +1,363 @@
+//! mod_ssl's `SSLProtocol`, `SSLCipherSuite` and `SSLHonorCipherOrder`, and
+//! nginx's `ssl_protocols` and `ssl_ciphers`, as traefik `tls.options`.
+//!
+//! Hosts with the same settings share one set of options, named after what
+//! it contains so every host can be rendered on its own.
+
+use super::{traefik::TlsOptions, SslConfig};
+
+/// The protocols in the order of their versions, with Go's names.
+const VERSIONS: [(&str, &str); 4] = [
+    ("TLSv1", "VersionTLS10"),
+    ("TLSv1.1", "VersionTLS11"),
+    ("TLSv1.2", "VersionTLS12"),
+    ("TLSv1.3", "VersionTLS13"),
+];
+
+/// OpenSSL's names of the suites Go implements, with Go's names. TLS 1.3
+/// suites aren't configurable in Go and are always enabled.
+const CIPHER_SUITES: [(&str, &str); 22] = [
+    (
+        "ECDHE-ECDSA-AES128-GCM-SHA256",
+        "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+    ),
+    (
+        "ECDHE-RSA-AES128-GCM-SHA256",
+        "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+    ),
+    (
+        "ECDHE-ECDSA-AES256-GCM-SHA384",
+        "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+    ),
+    (
+        "ECDHE-RSA-AES256-GCM-SHA384",
+        "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+    ),
+    (
+        "ECDHE-ECDSA-CHACHA20-POLY1305",
+        "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+    ),
+    (
+        "ECDHE-RSA-CHACHA20-POLY1305",
+        "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+    ),
+    (
+        "ECDHE-ECDSA-AES128-SHA256",
+        "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256",
+    ),
+    (
+        "ECDHE-RSA-AES128-SHA256",
+        "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+    ),
+    (
+        "ECDHE-ECDSA-AES128-SHA",
+        "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+    ),
+    ("ECDHE-RSA-AES128-SHA", "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA"),
+    (
+        "ECDHE-ECDSA-AES256-SHA",
+        "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+    ),
+    ("ECDHE-RSA-AES256-SHA", "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA"),
+    ("ECDHE-ECDSA-RC4-SHA", "TLS_ECDHE_ECDSA_WITH_RC4_128_SHA"),
+    ("ECDHE-RSA-RC4-SHA", "TLS_ECDHE_RSA_WITH_RC4_128_SHA"),
+    (
+        "ECDHE-RSA-DES-CBC3-SHA",
+        "TLS_ECDHE_RSA_WITH_3DES_EDE_CBC_SHA",
+    ),
+    ("AES128-GCM-SHA256", "TLS_RSA_WITH_AES_128_GCM_SHA256"),
+    ("AES256-GCM-SHA384", "TLS_RSA_WITH_AES_256_GCM_SHA384"),
+    ("AES128-SHA256", "TLS_RSA_WITH_AES_128_CBC_SHA256"),
+    ("AES128-SHA", "TLS_RSA_WITH_AES_128_CBC_SHA"),
+    ("AES256-SHA", "TLS_RSA_WITH_AES_256_CBC_SHA"),
+    ("DES-CBC3-SHA", "TLS_RSA_WITH_3DES_EDE_CBC_SHA"),
+    ("RC4-SHA", "TLS_RSA_WITH_RC4_128_SHA"),
+];
+
+/// The options matching the host's protocols and ciphers with their name,
+/// or none when it leaves both to the defaults. What traefik can't express
+/// is left to its defaults and added to `notes`.
+pub(super) fn to_tls_options(
+    ssl_config: &SslConfig,
+    notes: &mut Vec<String>,
+) -> Option<(String, TlsOptions)> {
+    let mut options = TlsOptions::default();
+    if ssl_config.protocols.is_some() {
+        match versions(&ssl_config.enabled_protocols(), notes) {
+            Ok((min_version, max_version)) => {
+                options.min_version = Some(min_version);
+                options.max_version = max_version;
+            }
+            Err(reason) => notes.push(format!("SSLProtocol isn't translated: {reason}")),
+        }
+    }
+    if let Some(ciphers) = &ssl_config.ciphers {
+        match cipher_suites(ciphers, notes) {
+            Ok(cipher_suites) => options.cipher_suites = cipher_suites,
+            Err(reason) => notes.push(format!(
+                "SSLCipherSuite {ciphers} isn't translated: {reason}"
+            )),
+        }
+        if ssl_config.honor_cipher_order == Some(true) {
+            notes.push("SSLHonorCipherOrder is dropped, Go orders the suites itself".to_string());
+        }
+    }
+    if options == TlsOptions::default() {
+        return None;
+    }
+    Some((options_name(&options), options))
+}
+
+/// Go's lowest and highest versions of the enabled protocols; the highest
+/// is left out when it is the latest one.
+fn versions(
+    protocols: &[String],
+    notes: &mut Vec<String>,
+) -> Result<(String, Option<String>), String> {
+    let mut enabled = vec![];
+    for protocol in protocols {
+        match VERSIONS
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(protocol))
+        {
+            Some(index) => enabled.push(index),
+            None => notes.push(format!(
+                "{protocol} isn't supported by traefik, it is left out"
+            )),
+        }
+    }
+    let (Some(&min), Some(&max)) = (enabled.iter().min(), enabled.iter().max()) else {
+        return Err("none of the protocols enabled is supported".to_string());
+    };
+    if max - min + 1 != enabled.len() {
+        notes.push(format!(
+            "{} leaves out versions in between, traefik enables every version from {} to {}",
+            protocols.join(" "),
+            VERSIONS[min].0,
+            VERSIONS[max].0
+        ));
+    }
+    let max_version = (max + 1 < VERSIONS.len()).then(|| VERSIONS[max].1.to_string());
+    Ok((VERSIONS[min].1.to_string(), max_version))
+}
+
+/// Go's names of the suites in an OpenSSL cipher list such as
+/// `ECDHE-RSA-AES128-GCM-SHA256:!aNULL`. Only suites named one by one can
+/// be listed; groups such as `HIGH` would need OpenSSL to expand them.
+fn cipher_suites(ciphers: &str, notes: &mut Vec<String>) -> Result<Vec<String>, String> {
+    let mut suites: Vec<String> = vec![];
+    let mut excluded = vec![];
+    for item in ciphers
+        .split([':', ',', ' '])
+        .filter(|item| !item.is_empty())
+    {
+        if let Some(name) = item.strip_prefix(['!', '-']) {
+            excluded.push(name);
+            continue;
+        }
+        // `@STRENGTH` and the like only sort the list
+        if item.starts_with('@') || item.starts_with('+') {
+            continue;
+        }
+        // TLS 1.3 suites, which Go always enables
+        if item.starts_with("TLS_") {
+            continue;
+        }
+        match CIPHER_SUITES
+            .iter()
+            .find(|(openssl, _)| openssl.eq_ignore_ascii_case(item))
+        {
+            Some((_, go)) if !suites.iter().any(|suite| suite == go) => suites.push(go.to_string()),
+            Some(_) => {}
+            // suite names have dashes, group names don't
+            None if item.contains('-') => notes.push(format!(
+                "cipher {item} isn't supported by traefik, it is left out"
+            )),
+            None => return Err(format!("{item} is a group of ciphers")),
+        }
+    }
+    suites.retain(|suite| {
+        !CIPHER_SUITES
+            .iter()
+            .any(|(openssl, go)| go == suite && excluded.contains(openssl))
+    });
+    if suites.is_empty() {
+        return Err("none of the ciphers is supported".to_string());
+    }
+    Ok(suites)
+}
+
+/// `ssl-tls12`, `ssl-tls10-tls12`, with a digest of the cipher suites
+/// when there are any, e.g. `ssl-tls12-1b2f9c0e`.
+fn options_name(options: &TlsOptions) -> String {
+    let mut name = "ssl".to_string();
+    let short = |version: &str| version.replace("VersionTLS", "tls");
+    for version in options.min_version.iter().chain(&options.max_version) {
+        name.push('-');
+        name.push_str(&short(version));
+    }
+    if !options.cipher_suites.is_empty() {
+        name.push_str(&format!("-{:08x}", fnv1a(&options.cipher_suites.join(":"))));
+    }
+    name
+}
+
+/// FNV-1a, which unlike std's hasher gives the same digest on every
+/// release.
+fn fnv1a(data: &str) -> u32 {
+    data.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssl_config(directives: &[(&str, &str)]) -> SslConfig {
+        let mut ssl_config = SslConfig::default();
+        for (name, args) in directives {
+            let args = args.split(' ').map(str::to_string).collect::<Vec<_>>();
+            assert!(ssl_config.set(name, &args), "{name} isn't recorded");
+        }
+        ssl_config
+    }
+
+    type VersionRange = Result<(String, Option<String>), String>;
+
+    fn version_range(protocols: &str) -> (VersionRange, Vec<String>) {
+        let mut notes = vec![];
+        let ssl_config = ssl_config(&[("SSLProtocol", protocols)]);
+        let range = versions(&ssl_config.enabled_protocols(), &mut notes);
+        (range, notes)
+    }
+
+    #[test]
+    fn protocol_versions() {
+        let cases = [
+            ("all -SSLv3 -TLSv1 -TLSv1.1", "VersionTLS12", None),
+            ("TLSv1.2", "VersionTLS12", Some("VersionTLS12")),
+            ("+TLSv1.2 +TLSv1.3", "VersionTLS12", None),
+            ("TLSv1 TLSv1.1", "VersionTLS10", Some("VersionTLS11")),
+            ("ALL", "VersionTLS10", None),
+        ];
+        for (protocols, min, max) in cases {
+            let (range, notes) = version_range(protocols);
+            assert_eq!(
+                range,
+                Ok((min.to_string(), max.map(str::to_string))),
+                "{protocols}"
+            );
+            assert!(notes.is_empty(), "{protocols}: {notes:?}");
+        }
+    }
+
+    #[test]
+    fn protocol_gaps_and_unsupported_versions_are_noted() {
+        let (range, notes) = version_range("TLSv1 TLSv1.2");
+        assert_eq!(
+            range,
+            Ok(("VersionTLS10".to_string(), Some("VersionTLS12".to_string())))
+        );
+        assert_eq!(notes.len(), 1);
+
+        let (range, notes) = version_range("SSLv3 TLSv1.2");
+        assert_eq!(
+            range,
+            Ok(("VersionTLS12".to_string(), Some("VersionTLS12".to_string())))
+        );
+        assert_eq!(notes, ["SSLv3 isn't supported by traefik, it is left out"]);
+
+        let (range, _) = version_range("SSLv3");
+        assert!(range.is_err());
+    }
+
+    #[test]
+    fn cipher_suite_names() {
+        let cases: [(&str, Result<&[&str], ()>); 6] = [
+            (
+                "ECDHE-RSA-AES128-GCM-SHA256:ECDHE-RSA-AES256-GCM-SHA384",
+                Ok(&[
+                    "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+                    "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+                ]),
+            ),
+            (
+                "ecdhe-ecdsa-chacha20-poly1305 !aNULL:!MD5 @STRENGTH",
+                Ok(&["TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256"]),
+            ),
+            (
+                "TLS_AES_128_GCM_SHA256:AES128-SHA:AES128-SHA",
+                Ok(&["TLS_RSA_WITH_AES_128_CBC_SHA"]),
+            ),
+            (
+                "AES128-SHA:AES256-SHA:!AES256-SHA",
+                Ok(&["TLS_RSA_WITH_AES_128_CBC_SHA"]),
+            ),
+            ("HIGH:!aNULL", Err(())),
+            ("AES128-SHA:-AES128-SHA", Err(())),
+        ];
+        for (ciphers, expected) in cases {
+            let suites = cipher_suites(ciphers, &mut vec![]).map_err(|_| ());
+            let expected = expected.map(|suites| suites.iter().map(|s| s.to_string()).collect());
+            assert_eq!(suites, expected, "{ciphers}");
+        }
+    }
+
+    #[test]
+    fn unknown_ciphers_are_noted() {
+        let mut notes = vec![];
+        let suites = cipher_suites("DHE-RSA-AES128-GCM-SHA256:AES128-SHA", &mut notes);
+        assert_eq!(suites, Ok(vec!["TLS_RSA_WITH_AES_128_CBC_SHA".to_string()]));
+        assert_eq!(
+            notes,
+            ["cipher DHE-RSA-AES128-GCM-SHA256 isn't supported by traefik, it is left out"]
+        );
+    }
+
+    #[test]
+    fn options_are_named_after_their_content() {
+        let options = |directives: &[(&str, &str)]| {
+            to_tls_options(&ssl_config(directives), &mut vec![]).map(|(name, _)| name)
+        };
+        assert_eq!(options(&[]), None);
+        assert_eq!(
+            options(&[("SSLProtocol", "all -SSLv3 -TLSv1 -TLSv1.1")]).as_deref(),
+            Some("ssl-tls12")
+        );
+        assert_eq!(
+            options(&[("SSLProtocol", "TLSv1 TLSv1.1")]).as_deref(),
+            Some("ssl-tls10-tls11")
+        );
+        let with_ciphers =
+            options(&[("SSLProtocol", "TLSv1.2"), ("SSLCipherSuite", "AES128-SHA")]).unwrap();
+        assert!(with_ciphers.starts_with("ssl-tls12-tls12-"));
+        assert_eq!(with_ciphers.len(), "ssl-tls12-tls12-".len() + 8);
+        // the same settings give the same name on every run
+        assert_eq!(
+            Some(with_ciphers),
+            options(&[("SSLProtocol", "TLSv1.2"), ("SSLCipherSuite", "AES128-SHA"),])
+        );
+    }
+
+    #[test]
+    fn honor_cipher_order_of_a_host_overrides_the_server() {
+        let server = ssl_config(&[("SSLHonorCipherOrder", "on")]);
+        let mut host = ssl_config(&[("SSLHonorCipherOrder", "off")]);
+        host.inherit(&server);
+        assert_eq!(host.honor_cipher_order, Some(false));
+
+        let mut host = SslConfig::default();
+        host.inherit(&server);
+        assert_eq!(host.honor_cipher_order, Some(true));
+
+        let mut notes = vec![];
+        let mut host = ssl_config(&[("SSLEngine", "on"), ("SSLCipherSuite", "AES128-SHA")]);
+        host.inherit(&server);
+        to_tls_options(&host, &mut notes);
+        assert_eq!(
+            notes,
+            ["SSLHonorCipherOrder is dropped, Go orders the suites itself"]
+        );
+    }
+}
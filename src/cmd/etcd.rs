@@ -0,0 +1,529 @@
+//! Writes the generated Traefik configuration straight into etcd v3.
+//!
+//! The writer only depends on the [`EtcdClient`] trait, so it can be pointed
+//! at a real cluster through [`HttpEtcdClient`] (the v3 JSON gateway) or at
+//! the in-process [`MemoryEtcdClient`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{debug, info};
+use serde_json::{json, Value};
+
+use crate::{
+    cmd::configs::ProxyConfig,
+    error::{ParserError, ParserResult},
+};
+
+pub trait EtcdClient {
+    /// Lists every key starting with `prefix`.
+    fn keys(&mut self, prefix: &str) -> ParserResult<Vec<String>>;
+
+    /// Deletes the keys and writes all pairs in a single transaction. etcd
+    /// refuses transactions touching a key twice, so the keys mustn't be
+    /// among the pairs.
+    fn txn(&mut self, deletes: &[String], pairs: &[(String, String)]) -> ParserResult<()>;
+
+    /// Writes all pairs in a single transaction.
+    fn put_all(&mut self, pairs: &[(String, String)]) -> ParserResult<()> {
+        self.txn(&[], pairs)
+    }
+
+    /// Deletes every key starting with `prefix`.
+    fn delete_prefix(&mut self, prefix: &str) -> ParserResult<()>;
+}
+
+/// Talks to etcd through the v3 gRPC JSON gateway, e.g.
+/// `http://127.0.0.1:2379/v3/kv/txn`.
+pub struct HttpEtcdClient {
+    endpoint: String,
+}
+
+impl HttpEtcdClient {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn post(&self, path: &str, body: Value) -> ParserResult<Value> {
+        let url = format!("{}/v3/{}", self.endpoint, path);
+        debug!("POST {url} {body}");
+        let response = ureq::post(&url)
+            .send_json(body)
+            .map_err(|err| ParserError::Etcd(err.to_string()))?;
+        response
+            .into_json()
+            .map_err(|err| ParserError::Etcd(err.to_string()))
+    }
+}
+
+impl EtcdClient for HttpEtcdClient {
+    fn keys(&mut self, prefix: &str) -> ParserResult<Vec<String>> {
+        let response = self.post(
+            "kv/range",
+            json!({
+                "key": STANDARD.encode(prefix),
+                "range_end": STANDARD.encode(prefix_end(prefix)),
+                "keys_only": true,
+            }),
+        )?;
+        response["kvs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|kv| decode(&kv["key"]))
+            .collect()
+    }
+
+    fn txn(&mut self, deletes: &[String], pairs: &[(String, String)]) -> ParserResult<()> {
+        let deletes = deletes.iter().map(|key| {
+            json!({
+                "request_delete_range": {
+                    "key": STANDARD.encode(key),
+                }
+            })
+        });
+        let puts = pairs.iter().map(|(key, value)| {
+            json!({
+                "request_put": {
+                    "key": STANDARD.encode(key),
+                    "value": STANDARD.encode(value),
+                }
+            })
+        });
+        let success = deletes.chain(puts).collect::<Vec<_>>();
+        let response = self.post("kv/txn", json!({ "success": success }))?;
+        if response["succeeded"].as_bool() == Some(false) {
+            return Err(ParserError::Etcd("transaction was not applied".to_string()));
+        }
+        Ok(())
+    }
+
+    fn delete_prefix(&mut self, prefix: &str) -> ParserResult<()> {
+        self.post(
+            "kv/deleterange",
+            json!({
+                "key": STANDARD.encode(prefix),
+                "range_end": STANDARD.encode(prefix_end(prefix)),
+            }),
+        )?;
+        Ok(())
+    }
+}
+
+/// An in-process stand-in for etcd.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryEtcdClient {
+    pub store: BTreeMap<String, String>,
+}
+
+impl EtcdClient for MemoryEtcdClient {
+    fn keys(&mut self, prefix: &str) -> ParserResult<Vec<String>> {
+        Ok(self
+            .store
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn txn(&mut self, deletes: &[String], pairs: &[(String, String)]) -> ParserResult<()> {
+        for key in deletes {
+            self.store.remove(key);
+        }
+        self.store.extend(pairs.iter().cloned());
+        Ok(())
+    }
+
+    fn delete_prefix(&mut self, prefix: &str) -> ParserResult<()> {
+        self.store.retain(|key, _| !key.starts_with(prefix));
+        Ok(())
+    }
+}
+
+/// Reads from the wrapped client but only prints the writes.
+pub struct DryRunClient<C: EtcdClient> {
+    inner: C,
+}
+
+impl<C: EtcdClient> DryRunClient<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+impl<C: EtcdClient> EtcdClient for DryRunClient<C> {
+    fn keys(&mut self, prefix: &str) -> ParserResult<Vec<String>> {
+        self.inner.keys(prefix)
+    }
+
+    fn txn(&mut self, deletes: &[String], pairs: &[(String, String)]) -> ParserResult<()> {
+        println!("txn:");
+        for key in deletes {
+            println!("  del {key}");
+        }
+        for (key, value) in pairs {
+            println!("  put {key} {value}");
+        }
+        Ok(())
+    }
+
+    fn delete_prefix(&mut self, prefix: &str) -> ParserResult<()> {
+        println!("delete {prefix}*");
+        Ok(())
+    }
+}
+
+/// etcd's default `--max-txn-ops`.
+pub const DEFAULT_MAX_TXN_OPS: usize = 128;
+
+/// The objects `--prune` removes when no longer generated.
+const PRUNED_KINDS: [&str; 5] = [
+    "http/routers",
+    "http/services",
+    "http/serversTransports",
+    "http/middlewares",
+    "tls/options",
+];
+
+/// Writes every host with its own routers, services, transports and
+/// middlewares, in transactions of at most `max_txn_ops` operations, and,
+/// when `prune` is set, removes the objects that are no longer generated.
+pub fn apply(
+    client: &mut impl EtcdClient,
+    configs: &ProxyConfig,
+    prune: bool,
+    max_txn_ops: usize,
+) -> ParserResult<()> {
+    let prefix = configs.key_prefix();
+    // the certificates are all written here, so the whole list is replaced
+    let certificates = format!("{prefix}/tls/certificates/");
+    replace(
+        client,
+        prefix,
+        &configs.to_shared_config().to_kv_pairs(prefix),
+        &[certificates],
+        max_txn_ops,
+    )?;
+
+    for virtual_host in &configs.virtual_hosts {
+        let pairs = virtual_host.to_etcd_kv_pairs();
+        if pairs.is_empty() {
+            continue;
+        }
+        info!("Writing {} keys for {}", pairs.len(), virtual_host.name());
+        replace(client, prefix, &pairs, &[], max_txn_ops)?;
+    }
+
+    if prune {
+        let generated = configs.to_traefik_config().to_kv_pairs(prefix);
+        for kind in PRUNED_KINDS {
+            let kind_prefix = format!("{prefix}/{kind}/");
+            let current = generated
+                .iter()
+                .filter_map(|(key, _)| object_name(&kind_prefix, key))
+                .collect::<BTreeSet<_>>();
+            let existing = client
+                .keys(&kind_prefix)?
+                .iter()
+                .filter_map(|key| object_name(&kind_prefix, key))
+                .collect::<BTreeSet<_>>();
+            for stale in existing.difference(&current) {
+                info!("Removing stale {kind} {stale}");
+                client.delete_prefix(&format!("{kind_prefix}{stale}/"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the pairs along with deleting the keys left over from earlier
+/// runs under the objects they cover, such as `middlewares/2` of a router
+/// that now has two middlewares, or under `owned`.
+fn replace(
+    client: &mut impl EtcdClient,
+    prefix: &str,
+    pairs: &[(String, String)],
+    owned: &[String],
+    max_txn_ops: usize,
+) -> ParserResult<()> {
+    let objects = pairs
+        .iter()
+        .filter_map(|(key, _)| object_prefix(prefix, key))
+        .chain(owned.iter().cloned())
+        .collect::<BTreeSet<_>>();
+    let written = pairs.iter().map(|(key, _)| key).collect::<BTreeSet<_>>();
+    let mut stale = vec![];
+    for object in objects {
+        for key in client.keys(&object)? {
+            if !written.contains(&key) {
+                stale.push(key);
+            }
+        }
+    }
+    batched_txn(client, &stale, pairs, max_txn_ops)
+}
+
+/// Runs the deletes and then the writes in as few transactions as etcd
+/// accepts, each with at most `max_txn_ops` operations.
+fn batched_txn(
+    client: &mut impl EtcdClient,
+    mut deletes: &[String],
+    mut pairs: &[(String, String)],
+    max_txn_ops: usize,
+) -> ParserResult<()> {
+    let max_txn_ops = max_txn_ops.max(1);
+    while !deletes.is_empty() || !pairs.is_empty() {
+        let (batch_deletes, rest) = deletes.split_at(deletes.len().min(max_txn_ops));
+        let room = max_txn_ops - batch_deletes.len();
+        let (batch_pairs, rest_pairs) = pairs.split_at(pairs.len().min(room));
+        client.txn(batch_deletes, batch_pairs)?;
+        deletes = rest;
+        pairs = rest_pairs;
+    }
+    Ok(())
+}
+
+/// The prefix of the object a key belongs to: `traefik/http/routers/www/`
+/// for `traefik/http/routers/www/rule`, and the whole list for
+/// certificates, which are numbered.
+fn object_prefix(prefix: &str, key: &str) -> Option<String> {
+    let rest = key.strip_prefix(prefix)?.strip_prefix('/')?;
+    if rest.starts_with("tls/certificates/") {
+        return Some(format!("{prefix}/tls/certificates/"));
+    }
+    let mut segments = rest.splitn(4, '/');
+    let (section, kind, name) = (segments.next()?, segments.next()?, segments.next()?);
+    segments.next()?;
+    Some(format!("{prefix}/{section}/{kind}/{name}/"))
+}
+
+/// The name of the object under `kind_prefix` a key belongs to.
+fn object_name(kind_prefix: &str, key: &str) -> Option<String> {
+    key.strip_prefix(kind_prefix)?
+        .split('/')
+        .next()
+        .map(str::to_string)
+}
+
+/// The smallest key greater than every key starting with `prefix`.
+fn prefix_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+fn decode(value: &Value) -> ParserResult<String> {
+    let bytes = STANDARD
+        .decode(value.as_str().unwrap_or_default())
+        .map_err(|err| ParserError::Etcd(err.to_string()))?;
+    String::from_utf8(bytes).map_err(|err| ParserError::Etcd(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use misc_conf::{apache::Apache, ast::*};
+
+    use super::*;
+    use crate::cmd::configs::registry::MiddlewareRegistry;
+
+    fn proxy_config(apache: &str) -> ProxyConfig {
+        let mut configs = ProxyConfig::default();
+        for directive in Directive::<Apache>::parse(apache.as_bytes()).unwrap() {
+            configs.merge(ProxyConfig::from(directive));
+        }
+        configs.inherit_server_config();
+        configs.apply_registry(&MiddlewareRegistry::default());
+        configs
+    }
+
+    fn keys(client: &mut MemoryEtcdClient, prefix: &str) -> Vec<String> {
+        client.keys(prefix).unwrap()
+    }
+
+    const HOST: &str = "
+<VirtualHost *:443>
+    ServerName www.example.com
+    SSLEngine on
+    ProxyPass /api http://app:3000/api
+    ProxyPass / http://app:3000/
+    <Location /api>
+        Require ip 10.0.0.0/8
+    </Location>
+</VirtualHost>
+";
+
+    const TLS_HOST: &str = "
+<VirtualHost *:443>
+    ServerName secure.example.com
+    SSLEngine on
+    SSLCertificateFile /etc/ssl/secure.pem
+    SSLCertificateKeyFile /etc/ssl/secure.key
+    SSLProtocol -all +TLSv1.2
+    ProxyPass / http://app:3000/
+</VirtualHost>
+";
+
+    /// Records the size of every transaction.
+    #[derive(Default)]
+    struct CountingClient {
+        inner: MemoryEtcdClient,
+        txns: Vec<usize>,
+    }
+
+    impl EtcdClient for CountingClient {
+        fn keys(&mut self, prefix: &str) -> ParserResult<Vec<String>> {
+            self.inner.keys(prefix)
+        }
+
+        fn txn(&mut self, deletes: &[String], pairs: &[(String, String)]) -> ParserResult<()> {
+            self.txns.push(deletes.len() + pairs.len());
+            self.inner.txn(deletes, pairs)
+        }
+
+        fn delete_prefix(&mut self, prefix: &str) -> ParserResult<()> {
+            self.inner.delete_prefix(prefix)
+        }
+    }
+
+    #[test]
+    fn apply_writes_every_host() {
+        let mut client = MemoryEtcdClient::default();
+        apply(&mut client, &proxy_config(HOST), false, DEFAULT_MAX_TXN_OPS).unwrap();
+        assert_eq!(
+            client.store["traefik/http/routers/www-example-com-api/rule"],
+            "Host(`www.example.com`) && PathPrefix(`/api`)"
+        );
+        assert_eq!(
+            client.store["traefik/http/services/www-example-com/loadBalancer/servers/0/url"],
+            "http://app:3000"
+        );
+    }
+
+    #[test]
+    fn apply_removes_leftover_keys_of_a_host() {
+        let mut client = MemoryEtcdClient::default();
+        let router = "traefik/http/routers/www-example-com-api";
+        client
+            .store
+            .insert(format!("{router}/middlewares/5"), "gone".to_string());
+        client.store.insert(
+            "traefik/tls/certificates/3/certFile".to_string(),
+            "old.pem".to_string(),
+        );
+        client.store.insert(
+            "traefik/http/routers/other/rule".to_string(),
+            "Host(`other`)".to_string(),
+        );
+        apply(&mut client, &proxy_config(HOST), false, DEFAULT_MAX_TXN_OPS).unwrap();
+        assert!(!client
+            .store
+            .contains_key(&format!("{router}/middlewares/5")));
+        assert!(client
+            .store
+            .contains_key(&format!("{router}/middlewares/0")));
+        assert!(keys(&mut client, "traefik/tls/certificates/").is_empty());
+        // without --prune other routers are left alone
+        assert!(client.store.contains_key("traefik/http/routers/other/rule"));
+    }
+
+    #[test]
+    fn prune_removes_every_kind_no_longer_generated() {
+        let mut client = MemoryEtcdClient::default();
+        for kind in PRUNED_KINDS {
+            client
+                .store
+                .insert(format!("traefik/{kind}/stale/key"), "value".to_string());
+        }
+        apply(
+            &mut client,
+            &proxy_config(TLS_HOST),
+            true,
+            DEFAULT_MAX_TXN_OPS,
+        )
+        .unwrap();
+        for kind in PRUNED_KINDS {
+            assert!(keys(&mut client, &format!("traefik/{kind}/stale/")).is_empty());
+        }
+        assert!(!keys(&mut client, "traefik/tls/options/").is_empty());
+        assert!(!keys(&mut client, "traefik/http/routers/secure-example-com/").is_empty());
+        assert!(!keys(&mut client, "traefik/http/middlewares/https-only/").is_empty());
+    }
+
+    #[test]
+    fn no_transaction_exceeds_the_limit() {
+        let hosts = (0..20)
+            .map(|i| {
+                format!(
+                    "<VirtualHost *:443>
+    ServerName www{i}.example.com
+    SSLEngine on
+    SSLCertificateFile /etc/ssl/www{i}.pem
+    SSLCertificateKeyFile /etc/ssl/www{i}.key
+    ProxyPass / http://app{i}:3000/
+    Header always set X-Frame-Options DENY
+</VirtualHost>
+"
+                )
+            })
+            .collect::<String>();
+        let configs = proxy_config(&hosts);
+        let mut client = CountingClient::default();
+        for i in 0..50 {
+            client.inner.store.insert(
+                format!("traefik/tls/certificates/{i}/certFile"),
+                "old.pem".to_string(),
+            );
+        }
+        apply(&mut client, &configs, true, 16).unwrap();
+        assert!(
+            client.txns.iter().all(|&ops| ops <= 16),
+            "{:?}",
+            client.txns
+        );
+
+        // the result is the same as with a single transaction per write
+        let mut unbatched = MemoryEtcdClient::default();
+        apply(&mut unbatched, &configs, true, usize::MAX).unwrap();
+        assert_eq!(client.inner.store, unbatched.store);
+        assert_eq!(keys(&mut unbatched, "traefik/tls/certificates/").len(), 40);
+    }
+
+    #[test]
+    fn host_middlewares_are_written_with_the_host() {
+        let configs = proxy_config(HOST);
+        let mut client = CountingClient::default();
+        apply(&mut client, &configs, false, DEFAULT_MAX_TXN_OPS).unwrap();
+        let shared = configs.to_shared_config().to_kv_pairs("traefik");
+        assert!(shared
+            .iter()
+            .all(|(key, _)| !key.starts_with("traefik/http/middlewares/www-example-com")));
+        let host = configs.virtual_hosts[0].to_etcd_kv_pairs();
+        assert!(host
+            .iter()
+            .any(|(key, _)| key.starts_with("traefik/http/middlewares/www-example-com")));
+    }
+
+    #[test]
+    fn object_prefixes() {
+        assert_eq!(
+            object_prefix("traefik", "traefik/http/routers/www/middlewares/0").as_deref(),
+            Some("traefik/http/routers/www/")
+        );
+        assert_eq!(
+            object_prefix("traefik", "traefik/tls/certificates/1/keyFile").as_deref(),
+            Some("traefik/tls/certificates/")
+        );
+        assert_eq!(
+            object_prefix("traefik", "other/http/routers/www/rule"),
+            None
+        );
+    }
+}
@@ -109,7 +109,7 @@ impl Interact {
         })
     }
 
-    fn parse_query_selector(text: &str, items: &Vec<String>) -> Vec<String> {
+    fn parse_query_selector(text: &str, items: &[String]) -> Vec<String> {
         text.parse::<usize>()
             .map(|query| {
                 items
@@ -118,7 +118,7 @@ impl Interact {
                     .map(|s| s.to_string())
                     .collect::<Vec<String>>()
             })
-            .unwrap_or(items.clone())
+            .unwrap_or(items.to_owned())
     }
 
     pub fn get_prompt(self) -> Prompt<Self> {
@@ -1,15 +1,27 @@
-use std::{collections::HashMap, ffi::OsStr, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
 use clap::Parser;
-use log::{debug, info};
-use misc_conf::apache::Apache;
+use log::{debug, info, warn};
+use misc_conf::{apache::Apache, ast::Directive, nginx::Nginx};
 
 use crate::{
-    cmd::configs::{ProxyConfig, VirtualHostBuilder},
-    error::ParserResult,
+    cmd::configs::{
+        apache::{ApacheContext, IncludeResolver},
+        etcdctl_commands,
+        profile::{Profile, DEFAULT_PROFILE},
+        re2::Compatibility,
+        registry::MiddlewareRegistry,
+        ProxyConfig, VirtualHostBuilder,
+    },
+    error::{ParserError, ParserResult},
 };
 
 pub mod configs;
+pub mod etcd;
 mod interact;
 mod logging;
 
@@ -21,11 +33,32 @@ pub struct Cli {
 
     #[arg(
         value_name = "starting_dir",
-        help = "The path to the config files",
+        help = "The path to the config files, or to httpd.conf to follow its Include directives",
         index = 1
     )]
     pub starting_dir: PathBuf,
 
+    #[arg(
+        long,
+        help = "ServerRoot used for relative includes (defaults to the directory of httpd.conf)"
+    )]
+    pub server_root: Option<PathBuf>,
+
+    #[arg(
+        short('D'),
+        long,
+        value_name = "NAME[=VALUE]",
+        help = "Define a parameter for <IfDefine> and ${VAR}, like httpd -D"
+    )]
+    pub define: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "MODULE",
+        help = "Treat a module as loaded for <IfModule>, e.g. mod_ssl.c or ssl_module (ssl, rewrite, proxy, headers and auth are assumed when parsing a directory)"
+    )]
+    pub module: Vec<String>,
+
     #[arg(
         short,
         long,
@@ -50,6 +83,73 @@ pub struct Cli {
         default_value = "etcd"
     )]
     pub config_type: String,
+
+    #[arg(
+        short('t'),
+        long,
+        help = "Type of the input files (auto, apache or nginx)",
+        default_value = "auto"
+    )]
+    pub input_type: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Write one file per host into this directory instead of printing (nginx)"
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    #[arg(long, help = "Report how every regex fares under traefik's RE2 engine")]
+    pub check_regexes: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Shared middlewares and the hosts to attach them to (toml or yaml)"
+    )]
+    pub middlewares: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Defaults and per-host overrides for the generated traefik config (defaults to ./proxyparser.toml when present)"
+    )]
+    pub profile: Option<PathBuf>,
+
+    #[arg(long, help = "Hide secrets such as password hashes in printed output")]
+    pub redact: bool,
+
+    #[arg(
+        long,
+        help = "Write the certificate and SSLCertificateChainFile into the combined files traefik is pointed at"
+    )]
+    pub write_chains: bool,
+
+    #[arg(long, help = "Write the traefik config straight into etcd")]
+    pub apply: bool,
+
+    #[arg(
+        long,
+        help = "The etcd endpoint used by --apply",
+        default_value = "http://127.0.0.1:2379"
+    )]
+    pub etcd_endpoint: String,
+
+    #[arg(long, help = "Show the keys --apply would write without writing them")]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        help = "Delete routers, services, transports, middlewares and TLS options in etcd that are no longer generated"
+    )]
+    pub prune: bool,
+
+    #[arg(
+        long,
+        help = "The most operations --apply puts in one etcd transaction, etcd's --max-txn-ops",
+        default_value_t = etcd::DEFAULT_MAX_TXN_OPS
+    )]
+    pub max_txn_ops: usize,
 }
 
 pub fn exec() -> ParserResult<()> {
@@ -65,64 +165,217 @@ pub fn exec() -> ParserResult<()> {
     use walkdir::WalkDir;
 
     let mut configs = ProxyConfig::default();
-    for entry in WalkDir::new(&starting_dir)
-        .into_iter()
-        .filter_map(Result::ok)
-    {
-        if entry
-            .path()
-            .extension()
-            .map_or(false, |ext| ext == extension)
+    let mut server_root = args.server_root.clone();
+    let follow_includes = starting_dir.is_file()
+        && match args.input_type.as_str() {
+            "apache" => true,
+            "nginx" => false,
+            _ => {
+                starting_dir.extension() != Some(OsStr::new("xlsx"))
+                    && !is_nginx_config(&std::fs::read(&starting_dir)?)
+            }
+        };
+    if follow_includes {
+        let root = server_root.clone().unwrap_or_else(|| {
+            starting_dir
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default()
+        });
+        info!("Loading {:?} with ServerRoot {:?}", starting_dir, root);
+        let context = ApacheContext::new(&args.module, &args.define);
+        let mut resolver = IncludeResolver::new(root, context);
+        let directives = resolver.load(&starting_dir)?;
+        // the resolver lists the file of every top-level <VirtualHost>
+        let mut sources = resolver.sources().iter();
+        for directive in directives {
+            let source = if directive.name.eq_ignore_ascii_case("virtualhost") {
+                sources.next()
+            } else {
+                None
+            };
+            let mut pc = ProxyConfig::from(directive);
+            if let Some(source) = source {
+                pc.set_source(source);
+            }
+            configs.merge(pc);
+        }
+        server_root = Some(resolver.server_root().to_path_buf());
+    } else {
+        // without httpd.conf there are no LoadModule lines to go by
+        let context = ApacheContext::new(&args.module, &args.define).with_common_modules();
+        for entry in WalkDir::new(&starting_dir)
+            .into_iter()
+            .filter_map(Result::ok)
         {
-            debug!("Processing file: {:?}", entry.path());
-            let pc = process(entry.path().to_path_buf())?;
-            pc.virtual_hosts.into_iter().for_each(|virtual_host| {
-                configs.add_virtual_host(virtual_host);
-            });
+            if is_wanted(entry.path(), extension, &args.input_type) {
+                debug!("Processing file: {:?}", entry.path());
+                let mut pc = process(entry.path().to_path_buf(), &args.input_type, &context)?;
+                pc.set_source(entry.path());
+                configs.merge(pc);
+            }
         }
     }
+    configs.inherit_server_config();
+    configs.load_user_files(server_root.as_deref());
+    configs.resolve_certificate_files(server_root.as_deref());
+    if args.write_chains && args.dry_run {
+        info!("Not writing the certificate chains, --dry-run is set");
+    }
+    configs.write_certificate_chains(args.write_chains && !args.dry_run)?;
+    let registry = match &args.middlewares {
+        Some(path) => MiddlewareRegistry::load(path)?,
+        None => MiddlewareRegistry::default(),
+    };
+    configs.apply_registry(&registry);
+    let profile_path = args.profile.clone().or_else(|| {
+        let path = PathBuf::from(DEFAULT_PROFILE);
+        path.exists().then_some(path)
+    });
+    let profile = match profile_path {
+        Some(path) => {
+            info!("Using profile {:?}", path);
+            Profile::load(&path)?
+        }
+        None => Profile::default(),
+    };
+    configs.apply_profile(&profile);
 
     // if args.interactive {
     //     let _ = interact::exec(configs);
     // }
 
+    let redacted;
+    let printed = if args.redact {
+        redacted = configs.redacted();
+        &redacted
+    } else {
+        &configs
+    };
+
     if args.print_middleware_commands {
-        print_middleware_commands(&configs);
+        print_middleware_commands(printed);
         println!("\n");
     }
 
+    if args.check_regexes {
+        print_regex_checks(&configs);
+    }
+
     if args.print_commands {
-        print_commands(&configs, &args.config_type);
+        print_commands(printed, &args.config_type, args.output_dir.as_deref())?;
+    }
+
+    if args.apply {
+        let mut client = etcd::HttpEtcdClient::new(&args.etcd_endpoint);
+        if args.dry_run {
+            let mut client = etcd::DryRunClient::new(client);
+            etcd::apply(&mut client, printed, args.prune, args.max_txn_ops)?;
+        } else {
+            etcd::apply(&mut client, &configs, args.prune, args.max_txn_ops)?;
+        }
     }
 
     Ok(())
 }
 
-fn process(file_path: PathBuf) -> ParserResult<ProxyConfig> {
+fn process(
+    file_path: PathBuf,
+    input_type: &str,
+    context: &ApacheContext,
+) -> ParserResult<ProxyConfig> {
     info!("Processing file: {:?}", file_path);
     let extension: &OsStr = file_path.extension().unwrap_or(OsStr::new("conf"));
     let mut configs = ProxyConfig::default();
 
-    if extension == "conf" {
-        process_apache(file_path, &mut configs)?;
-    } else if extension == "xlsx" {
+    if extension == "xlsx" {
         process_xlsx(file_path, &mut configs)?;
+        return Ok(configs);
+    }
+
+    match input_type {
+        "apache" => process_apache(file_path, context, &mut configs)?,
+        "nginx" => process_nginx(file_path, &mut configs)?,
+        _ => {
+            if is_nginx_config(&std::fs::read(&file_path)?) {
+                process_nginx(file_path, &mut configs)?;
+            } else {
+                process_apache(file_path, context, &mut configs)?;
+            }
+        }
     }
 
     Ok(configs)
 }
 
-fn process_apache(file_path: PathBuf, configs: &mut ProxyConfig) -> ParserResult<()> {
+/// Whether the walk parses a file: files with the configured extension, and
+/// extensionless nginx files such as the ones in `sites-enabled/`.
+fn is_wanted(path: &Path, extension: &OsStr, input_type: &str) -> bool {
+    match path.extension() {
+        Some(ext) => ext == extension,
+        None => {
+            input_type != "apache"
+                && path.is_file()
+                && std::fs::read(path).is_ok_and(|data| is_nginx_config(&data))
+        }
+    }
+}
+
+/// Guesses whether a config file is written in the nginx dialect: nginx
+/// statements end with `;` or open a `{` block while apache sections are
+/// `<Tag>` delimited.
+fn is_nginx_config(data: &[u8]) -> bool {
+    let content = String::from_utf8_lossy(data);
+    let lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>();
+    !lines.iter().any(|line| line.starts_with('<'))
+        && lines
+            .iter()
+            .any(|line| line.ends_with(';') || line.ends_with('{'))
+}
+
+fn process_apache(
+    file_path: PathBuf,
+    context: &ApacheContext,
+    configs: &mut ProxyConfig,
+) -> ParserResult<()> {
     info!("Processing file: {:?}", file_path);
     use misc_conf::ast::*;
 
     let data = std::fs::read(file_path).expect("unable to read file");
     if let Ok(res) = Directive::<Apache>::parse(&data) {
-        for directive in res {
-            let pc = ProxyConfig::from(directive);
-            pc.virtual_hosts.into_iter().for_each(|virtual_host| {
-                configs.add_virtual_host(virtual_host);
-            });
+        // every file starts from the command line state, so the order files
+        // are walked in doesn't leak defines between them
+        let res = context.clone().evaluate(res);
+        add_apache_directives(res, configs);
+    }
+
+    Ok(())
+}
+
+fn add_apache_directives(directives: Vec<Directive<Apache>>, configs: &mut ProxyConfig) {
+    for directive in directives {
+        configs.merge(ProxyConfig::from(directive));
+    }
+}
+
+fn process_nginx(file_path: PathBuf, configs: &mut ProxyConfig) -> ParserResult<()> {
+    info!("Processing file: {:?}", file_path);
+    use misc_conf::ast::*;
+
+    let data = std::fs::read(file_path).expect("unable to read file");
+    match Directive::<Nginx>::parse(&data) {
+        Ok(res) => {
+            for directive in res {
+                configs.merge(ProxyConfig::from(directive));
+            }
+        }
+        Err(err) => {
+            warn!("Unable to parse nginx config");
+            debug!("{:?}", err);
         }
     }
 
@@ -189,8 +442,31 @@ fn process_xlsx(file_path: PathBuf, configs: &mut ProxyConfig) -> ParserResult<(
     Ok(())
 }
 
-fn print_commands(configs: &ProxyConfig, config_type: &str) {
+fn print_commands(
+    configs: &ProxyConfig,
+    config_type: &str,
+    output_dir: Option<&Path>,
+) -> ParserResult<()> {
     let mut json_configs = Vec::new();
+    let mut written = HashSet::new();
+    if config_type == "etcd" {
+        let shared = configs.to_shared_config().to_kv_pairs(configs.key_prefix());
+        println!("{}", etcdctl_commands(&shared));
+    }
+    if config_type == "nginx" {
+        let upstreams = configs.to_nginx_upstreams();
+        match output_dir {
+            Some(dir) if !upstreams.is_empty() => {
+                std::fs::create_dir_all(dir)?;
+                let file_path = dir.join("upstreams.conf");
+                info!("Writing file: {:?}", file_path);
+                std::fs::write(&file_path, upstreams)?;
+                written.insert(file_path);
+            }
+            Some(_) => {}
+            None => print!("{upstreams}"),
+        }
+    }
     for virtual_host in &configs.virtual_hosts {
         match config_type {
             "etcd" => println!("{}\n", virtual_host.to_etcd_config()),
@@ -199,29 +475,145 @@ fn print_commands(configs: &ProxyConfig, config_type: &str) {
                     json_configs.push(json_config);
                 }
             }
+            "nginx" => {
+                let nginx_config = virtual_host.to_nginx_config();
+                if nginx_config.is_empty() {
+                    continue;
+                }
+                match output_dir {
+                    Some(dir) => {
+                        std::fs::create_dir_all(dir)?;
+                        let file_path = dir.join(format!("{}.conf", virtual_host.dashed_name()));
+                        if !written.insert(file_path.clone()) {
+                            return Err(ParserError::Config(format!(
+                                "{} would be written twice, {} is declared more than once on the same port",
+                                file_path.display(),
+                                virtual_host.name()
+                            )));
+                        }
+                        info!("Writing file: {:?}", file_path);
+                        std::fs::write(file_path, nginx_config)?;
+                    }
+                    None => println!("{}", nginx_config),
+                }
+            }
+            "traefik-yaml" | "traefik-toml" => {}
             _ => println!("Unknown config type"),
         }
     }
-    if config_type == "json" {
-        let j = serde_json::json!(json_configs);
-        println!("{}", j.to_string());
+    match config_type {
+        "json" => {
+            let j = serde_json::json!(json_configs);
+            println!("{}", j);
+        }
+        "traefik-yaml" => print!("{}", serde_yaml::to_string(&configs.to_traefik_config())?),
+        "traefik-toml" => print!("{}", toml::to_string(&configs.to_traefik_config())?),
+        _ => {}
     }
+    Ok(())
 }
 
-fn print_middleware_commands(_configs: &ProxyConfig) {
-    println!("etcdctl put traefik.http.middlewares.secured.chain.middlewares https-only");
-    println!("etcdctl put traefik/http/middlewares/https-only/redirectScheme/scheme https");
-    println!("etcdctl put traefik/http/middlewares/follow-redirects/redirectregex/permanent true");
-    println!("etcdctl put traefik.http.middlewares.secured.chain.middlewares https-only");
-    println!("etcdctl put traefik/http/middlewares/https-only/redirectScheme/scheme https");
-    println!("etcdctl put traefik/http/middlewares/https-only/redirectScheme/permanent true");
-    println!("etcdctl put traefik/http/middlewares/https-only/redirectScheme/port 443");
-
-    println!("etcdctl put traefik/http/middlewares/enable-headers/headers/accessControlAllowMethods \"GET, POST, OPTIONS, PUT, DELETE\"");
-    println!("etcdctl put traefik/http/middlewares/enable-headers/headers/accessControlAllowHeaders \"Content-Type, Content-Length, Accept-Encoding, X-CSRF-Token, Authorization, accept, origin, Cache-Control, X-Requested-With\"");
-    println!("etcdctl put traefik/http/middlewares/enable-headers/headers/accessControlAllowOriginList \"*\"");
-    println!(
-        "etcdctl put traefik/http/middlewares/enable-headers/headers/accessControlMaxAge \"3600\""
+fn print_regex_checks(configs: &ProxyConfig) {
+    for virtual_host in &configs.virtual_hosts {
+        let checks = virtual_host.regex_checks();
+        if checks.is_empty() {
+            continue;
+        }
+        println!("{}", virtual_host.name());
+        for check in checks {
+            let detail = match &check.compatibility {
+                Compatibility::Compatible => String::new(),
+                Compatibility::Convertible { pattern, changes } => {
+                    format!(" -> {pattern} ({})", changes.join(", "))
+                }
+                Compatibility::Unsupported { reason } => format!(" ({reason})"),
+            };
+            println!(
+                "  {:<12} {:<28} {}{detail}",
+                check.compatibility.status(),
+                check.source,
+                check.pattern
+            );
+        }
+    }
+}
+
+fn print_middleware_commands(configs: &ProxyConfig) {
+    let middlewares = configs.to_middleware_config();
+    print!(
+        "{}",
+        etcdctl_commands(&middlewares.to_kv_pairs(configs.key_prefix()))
     );
-    println!("etcdctl put traefik/http/middlewares/enable-headers/headers/addVaryHeader true");
+}
+
+#[cfg(test)]
+mod tests {
+    use misc_conf::ast::DirectiveTrait;
+
+    use super::*;
+
+    fn proxy_config(apache: &str) -> ProxyConfig {
+        let mut configs = ProxyConfig::default();
+        for directive in Directive::<Apache>::parse(apache.as_bytes()).unwrap() {
+            configs.merge(ProxyConfig::from(directive));
+        }
+        configs.inherit_server_config();
+        configs
+    }
+
+    #[test]
+    fn nginx_files_are_named_after_the_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let configs = proxy_config(
+            "
+<VirtualHost *:443>
+    ServerName www.example.com
+    ProxyPass / http://app:3000/
+</VirtualHost>
+<VirtualHost *:80>
+    ServerName www.example.com
+    Redirect / https://www.example.com/
+</VirtualHost>
+<VirtualHost *:80>
+    ServerName api.example.com
+    ProxyPass / http://api:3000/
+</VirtualHost>
+",
+        );
+        print_commands(&configs, "nginx", Some(dir.path())).unwrap();
+        let mut files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "api-example-com.conf",
+                "www-example-com-443.conf",
+                "www-example-com-80.conf"
+            ]
+        );
+    }
+
+    #[test]
+    fn nginx_files_are_not_written_twice() {
+        let dir = tempfile::tempdir().unwrap();
+        let configs = proxy_config(
+            "
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyPass / http://app:3000/
+</VirtualHost>
+<VirtualHost *:80>
+    ServerName www.example.com
+    ProxyPass / http://other:3000/
+</VirtualHost>
+",
+        );
+        let err = print_commands(&configs, "nginx", Some(dir.path())).unwrap_err();
+        assert!(err.to_string().contains("would be written twice"), "{err}");
+        let written = std::fs::read_to_string(dir.path().join("www-example-com-80.conf")).unwrap();
+        assert!(written.contains("http://app:3000"));
+    }
 }
@@ -11,4 +11,14 @@ pub enum ParserError {
     Parse(#[from] calamine::Error),
     #[error("Deserialize error: {0}")]
     Deserialize(#[from] DeError),
+    #[error("unable to serialize yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("unable to serialize toml: {0}")]
+    Toml(#[from] toml::ser::Error),
+    #[error("unable to resolve include: {0}")]
+    Include(String),
+    #[error("etcd request failed: {0}")]
+    Etcd(String),
+    #[error("invalid config file: {0}")]
+    Config(String),
 }